@@ -5,37 +5,105 @@ pub use builder::BlockBuilder;
 use bytes::{Buf, BufMut, Bytes};
 pub use iterator::BlockIterator;
 
+use crate::varint::{get_varint, put_varint};
+
 pub const SIZEOF_U16: usize = std::mem::size_of::<u16>();
+pub const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+
+/// Number of entries between two restart points of a block.
+pub const DEFAULT_RESTART_INTERVAL: usize = 16;
 
 /// A block is the smallest unit of read and caching in LSM tree. It is a collection of sorted
 /// key-value pairs.
+///
+/// Each entry is encoded as `shared_len (varint) | unshared_len (varint) | unshared key | seq
+/// (varint) | kind (u8) | value_len (varint) | value`, where the first `shared_len` bytes of the
+/// key are the same as the previous key. Every few entries there is a restart point, whose key is
+/// stored in full (`shared_len` is 0), so that a lookup can binary search the restart points and
+/// only decode the entries after one. The offsets of the restart points (u32 each) and their number
+/// (u32) follow the entries.
 pub struct Block {
     data: Vec<u8>,
-    offsets: Vec<u16>,
+    /// Offsets of the restart points.
+    restarts: Vec<u32>,
 }
 
 impl Block {
     pub fn encode(&self) -> Bytes {
         let mut buf = self.data.clone();
-        let offsets_len = self.offsets.len();
-        for offset in &self.offsets {
-            buf.put_u16(*offset);
+        let restarts_len = self.restarts.len();
+        for offset in &self.restarts {
+            buf.put_u32(*offset);
         }
-        // Adds number of elements at the end of the block
-        buf.put_u16(offsets_len as u16);
+        // Adds number of restart points at the end of the block
+        buf.put_u32(restarts_len as u32);
         buf.into()
     }
 
     pub fn decode(data: &[u8]) -> Self {
-        let entry_offsets_len = (&data[data.len() - SIZEOF_U16..]).get_u16() as usize;
-        let data_end = data.len() - SIZEOF_U16 - entry_offsets_len * SIZEOF_U16;
-        let offsets_raw = &data[data_end..data.len() - SIZEOF_U16];
-        let offsets = offsets_raw
-            .chunks(SIZEOF_U16)
-            .map(|mut x| x.get_u16())
+        let restarts_len = (&data[data.len() - SIZEOF_U32..]).get_u32() as usize;
+        let data_end = data.len() - SIZEOF_U32 - restarts_len * SIZEOF_U32;
+        let restarts_raw = &data[data_end..data.len() - SIZEOF_U32];
+        let restarts = restarts_raw
+            .chunks(SIZEOF_U32)
+            .map(|mut x| x.get_u32())
             .collect();
         let data = data[0..data_end].to_vec();
-        Self { data, offsets }
+        Self { data, restarts }
+    }
+
+    /// Decode a block written in the first SST format, where the lengths in the entries, the
+    /// restart points and their number are all u16. The entries are converted to the current
+    /// encoding, so that the block can be read by [`BlockIterator`].
+    pub fn decode_v0(data: &[u8]) -> Self {
+        let restarts_len = (&data[data.len() - SIZEOF_U16..]).get_u16() as usize;
+        let data_end = data.len() - SIZEOF_U16 - restarts_len * SIZEOF_U16;
+        let restarts = data[data_end..data.len() - SIZEOF_U16]
+            .chunks(SIZEOF_U16)
+            .map(|mut x| x.get_u16() as usize);
+        Self::convert_entries(&data[..data_end], restarts, |buf| buf.get_u16() as usize)
+    }
+
+    /// Decode a block written in SST format 1 or 2, whose entries have no sequence number. The
+    /// entries are converted to the current encoding.
+    pub fn decode_v1(data: &[u8]) -> Self {
+        let block = Self::decode(data);
+        let restarts = block.restarts.iter().map(|offset| *offset as usize);
+        Self::convert_entries(&block.data, restarts, |buf| get_varint(buf) as usize)
+    }
+
+    /// Re-encode the entries of an older format, whose lengths are read by `get_len`. They are
+    /// given sequence number 0, so that they are older than any entry written since.
+    fn convert_entries(
+        data: &[u8],
+        restarts: impl Iterator<Item = usize>,
+        get_len: impl Fn(&mut &[u8]) -> usize,
+    ) -> Self {
+        let mut old_restarts = restarts.peekable();
+        let mut entries = data;
+        let mut block = Self {
+            data: Vec::with_capacity(data.len()),
+            restarts: Vec::new(),
+        };
+        while entries.has_remaining() {
+            if old_restarts.peek() == Some(&(data.len() - entries.remaining())) {
+                old_restarts.next();
+                block.restarts.push(block.data.len() as u32);
+            }
+            let shared = get_len(&mut entries);
+            let unshared = get_len(&mut entries);
+            put_varint(&mut block.data, shared as u64);
+            put_varint(&mut block.data, unshared as u64);
+            block.data.put_slice(&entries[..unshared]);
+            entries.advance(unshared);
+            put_varint(&mut block.data, 0);
+            block.data.put_u8(entries.get_u8());
+            let value_len = get_len(&mut entries);
+            put_varint(&mut block.data, value_len as u64);
+            block.data.put_slice(&entries[..value_len]);
+            entries.advance(value_len);
+        }
+        block
     }
 }
 
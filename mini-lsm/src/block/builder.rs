@@ -1,53 +1,99 @@
 use bytes::BufMut;
 
-use super::{Block, SIZEOF_U16};
+use super::{Block, DEFAULT_RESTART_INTERVAL, SIZEOF_U32};
+use crate::value::ValueKind;
+use crate::varint::{put_varint, varint_len};
 
 /// Builds a block.
 pub struct BlockBuilder {
-    /// Offsets of each key-value entries.
-    offsets: Vec<u16>,
+    /// Offsets of the restart points.
+    restarts: Vec<u32>,
     /// All key-value pairs in the block.
     data: Vec<u8>,
     /// The expected block size.
     block_size: usize,
+    /// Number of entries between two restart points.
+    restart_interval: usize,
+    /// Number of entries added since the last restart point.
+    counter: usize,
+    /// The last key added, which the next key is prefix compressed against.
+    last_key: Vec<u8>,
 }
 
 impl BlockBuilder {
     /// Creates a new block builder.
     pub fn new(block_size: usize) -> Self {
         Self {
-            offsets: Vec::new(),
+            restarts: Vec::new(),
             data: Vec::new(),
             block_size,
+            restart_interval: DEFAULT_RESTART_INTERVAL,
+            counter: 0,
+            last_key: Vec::new(),
         }
     }
 
+    /// Set the number of entries between two restart points.
+    pub fn with_restart_interval(mut self, restart_interval: usize) -> Self {
+        assert!(restart_interval > 0, "restart interval must be positive");
+        self.restart_interval = restart_interval;
+        self
+    }
+
     fn estimated_size(&self) -> usize {
-        self.offsets.len() * SIZEOF_U16 + self.data.len() + SIZEOF_U16
+        self.restarts.len() * SIZEOF_U32 + self.data.len() + SIZEOF_U32
     }
 
     /// Adds a key-value pair to the block. Returns false when the block is full.
     #[must_use]
     pub fn add(&mut self, key: &[u8], value: &[u8]) -> bool {
+        self.add_entry(key, 0, ValueKind::Put, value)
+    }
+
+    /// Adds a version of a key to the block. Returns false when the block is full. An entry is
+    /// always added to an empty block, even if it is larger than the block size.
+    #[must_use]
+    pub fn add_entry(&mut self, key: &[u8], seq: u64, kind: ValueKind, value: &[u8]) -> bool {
         assert!(!key.is_empty(), "key must not be empty");
-        // The overhead here is `key_len` + `val_len` + `offset`, each is of type `u16`
-        if self.estimated_size() + key.len() + value.len() + SIZEOF_U16 * 3 > self.block_size
-            && !self.is_empty()
-        {
+        // The overhead here is `shared_len` + `unshared_len` + `val_len`, the sequence number, the
+        // kind, and a restart point. Assume the key is not compressed.
+        let entry_size = varint_len(key.len() as u64) * 2
+            + key.len()
+            + varint_len(seq)
+            + 1
+            + varint_len(value.len() as u64)
+            + value.len()
+            + SIZEOF_U32;
+        if self.estimated_size() + entry_size > self.block_size && !self.is_empty() {
             return false;
         }
-        // The offsets should be updated at first, to maintain the correct offset
-        self.offsets.push(self.data.len() as u16);
-        self.data.put_u16(key.len() as u16);
-        self.data.put(key);
-        self.data.put_u16(value.len() as u16);
+        let shared = if self.counter % self.restart_interval == 0 {
+            self.restarts.push(self.data.len() as u32);
+            self.counter = 0;
+            0
+        } else {
+            self.last_key
+                .iter()
+                .zip(key)
+                .take_while(|(a, b)| a == b)
+                .count()
+        };
+        self.counter += 1;
+        put_varint(&mut self.data, shared as u64);
+        put_varint(&mut self.data, (key.len() - shared) as u64);
+        self.data.put(&key[shared..]);
+        put_varint(&mut self.data, seq);
+        self.data.put_u8(kind.encode());
+        put_varint(&mut self.data, value.len() as u64);
         self.data.put(value);
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
         true
     }
 
     /// Check if there is no key-value pair in the block.
     pub fn is_empty(&self) -> bool {
-        self.offsets.is_empty()
+        self.restarts.is_empty()
     }
 
     /// Finalize the block.
@@ -57,7 +103,7 @@ impl BlockBuilder {
         }
         Block {
             data: self.data,
-            offsets: self.offsets,
+            restarts: self.restarts,
         }
     }
 }
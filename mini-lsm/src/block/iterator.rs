@@ -3,13 +3,18 @@ use std::sync::Arc;
 use bytes::Buf;
 
 use super::Block;
+use crate::value::ValueKind;
+use crate::varint::get_varint;
 
 /// Iterates on a block.
 pub struct BlockIterator {
     block: Arc<Block>,
     key: Vec<u8>,
     value: Vec<u8>,
-    idx: usize,
+    seq: u64,
+    kind: ValueKind,
+    /// Offset of the entry after the current one.
+    next_offset: usize,
 }
 
 impl BlockIterator {
@@ -18,7 +23,9 @@ impl BlockIterator {
             block,
             key: Vec::new(),
             value: Vec::new(),
-            idx: 0,
+            seq: 0,
+            kind: ValueKind::Put,
+            next_offset: 0,
         }
     }
 
@@ -29,7 +36,7 @@ impl BlockIterator {
         iter
     }
 
-    /// Creates a block iterator and seek to the first key that >= `key`.
+    /// Creates a block iterator and seek to the newest version of the first key that >= `key`.
     pub fn create_and_seek_to_key(block: Arc<Block>, key: &[u8]) -> Self {
         let mut iter = Self::new(block);
         iter.seek_to_key(key);
@@ -48,6 +55,18 @@ impl BlockIterator {
         &self.value
     }
 
+    /// Returns the sequence number of the current entry.
+    pub fn seq(&self) -> u64 {
+        debug_assert!(!self.key.is_empty(), "invalid iterator");
+        self.seq
+    }
+
+    /// Returns the kind of the current entry.
+    pub fn kind(&self) -> ValueKind {
+        debug_assert!(!self.key.is_empty(), "invalid iterator");
+        self.kind
+    }
+
     /// Returns true if the iterator is valid.
     pub fn is_valid(&self) -> bool {
         !self.key.is_empty()
@@ -55,59 +74,69 @@ impl BlockIterator {
 
     /// Seeks to the first key in the block.
     pub fn seek_to_first(&mut self) {
-        self.seek_to(0);
+        self.seek_to_restart(0);
     }
 
-    /// Seeks to the idx-th key in the block.
-    fn seek_to(&mut self, idx: usize) {
-        if idx >= self.block.offsets.len() {
-            self.key.clear();
-            self.value.clear();
+    /// Seeks to the idx-th restart point in the block.
+    fn seek_to_restart(&mut self, idx: usize) {
+        self.key.clear();
+        self.value.clear();
+        if idx >= self.block.restarts.len() {
             return;
         }
-        let offset = self.block.offsets[idx] as usize;
-        self.seek_to_offset(offset);
-        self.idx = idx;
+        self.seek_to_offset(self.block.restarts[idx] as usize);
     }
 
     /// Move to the next key in the block.
     pub fn next(&mut self) {
-        self.idx += 1;
-        self.seek_to(self.idx);
+        if self.next_offset >= self.block.data.len() {
+            self.key.clear();
+            self.value.clear();
+            return;
+        }
+        self.seek_to_offset(self.next_offset);
     }
 
-    /// Seek to the specified position and update the current `key` and `value`
-    /// Index update will be handled by caller
+    /// Decode the entry at `offset` and update the current `key` and `value`. The current key must
+    /// be the key of the previous entry, unless the entry is a restart point.
     fn seek_to_offset(&mut self, offset: usize) {
         let mut entry = &self.block.data[offset..];
-        // Since `get_u16()` will automatically move the ptr 2 bytes ahead here,
+        // Since `get_varint()` will automatically move the ptr ahead here,
         // we don't need to manually advance it
-        let key_len = entry.get_u16() as usize;
-        let key = entry[..key_len].to_vec();
-        entry.advance(key_len);
-        self.key.clear();
-        self.key.extend(key);
-        let value_len = entry.get_u16() as usize;
-        let value = entry[..value_len].to_vec();
-        entry.advance(value_len);
+        let shared = get_varint(&mut entry) as usize;
+        let unshared = get_varint(&mut entry) as usize;
+        self.key.truncate(shared);
+        self.key.extend_from_slice(&entry[..unshared]);
+        entry.advance(unshared);
+        self.seq = get_varint(&mut entry);
+        // The block passed its checksum, so the kind can only be invalid because of a bug.
+        self.kind = ValueKind::decode(entry.get_u8()).expect("invalid value kind");
+        let value_len = get_varint(&mut entry) as usize;
         self.value.clear();
-        self.value.extend(value);
+        self.value.extend_from_slice(&entry[..value_len]);
+        entry.advance(value_len);
+        self.next_offset = self.block.data.len() - entry.len();
     }
 
-    /// Seek to the first key that is >= `key`.
+    /// Seek to the newest version of the first key that is >= `key`.
     pub fn seek_to_key(&mut self, key: &[u8]) {
+        // Find the last restart point whose key is < `key`, as the first key >= `key` is either
+        // after it or at the first restart point. A restart point whose key is `key` may have
+        // newer versions of the key before it.
         let mut low = 0;
-        let mut high = self.block.offsets.len();
+        let mut high = self.block.restarts.len();
         while low < high {
             let mid = low + (high - low) / 2;
-            self.seek_to(mid);
+            self.seek_to_restart(mid);
             assert!(self.is_valid());
             match self.key().cmp(key) {
                 std::cmp::Ordering::Less => low = mid + 1,
-                std::cmp::Ordering::Greater => high = mid,
-                std::cmp::Ordering::Equal => return,
+                std::cmp::Ordering::Greater | std::cmp::Ordering::Equal => high = mid,
             }
         }
-        self.seek_to(low);
+        self.seek_to_restart(low.saturating_sub(1));
+        while self.is_valid() && self.key() < key {
+            self.next();
+        }
     }
 }
@@ -3,6 +3,7 @@ use std::sync::Arc;
 use super::builder::BlockBuilder;
 use super::iterator::BlockIterator;
 use super::*;
+use crate::value::ValueKind;
 
 #[test]
 fn test_block_build_single_key() {
@@ -57,7 +58,7 @@ fn test_block_decode() {
     let block = generate_block();
     let encoded = block.encode();
     let decoded_block = Block::decode(&encoded);
-    assert_eq!(block.offsets, decoded_block.offsets);
+    assert_eq!(block.restarts, decoded_block.restarts);
     assert_eq!(block.data, decoded_block.data);
 }
 
@@ -120,3 +121,162 @@ fn test_block_seek_key() {
         iter.seek_to_key(b"k");
     }
 }
+
+fn generate_block_with_restart_interval(restart_interval: usize) -> Block {
+    let mut builder = BlockBuilder::new(10000).with_restart_interval(restart_interval);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(&key_of(idx), &value_of(idx)));
+    }
+    builder.build()
+}
+
+#[test]
+fn test_block_prefix_compression() {
+    let compressed = generate_block_with_restart_interval(16).encode();
+    let uncompressed = generate_block_with_restart_interval(1).encode();
+    // Each key shares at least `key_` with the previous one.
+    assert!(compressed.len() + 4 * (num_of_keys() - 7) < uncompressed.len());
+}
+
+#[test]
+fn test_block_seek_key_restart_intervals() {
+    for restart_interval in [1, 3, 16, 1000] {
+        let block = Arc::new(generate_block_with_restart_interval(restart_interval));
+        let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+        for i in 0..num_of_keys() {
+            assert_eq!(iter.key(), key_of(i));
+            assert_eq!(iter.value(), value_of(i));
+            iter.next();
+        }
+        assert!(!iter.is_valid());
+        for i in 0..num_of_keys() {
+            let iter = BlockIterator::create_and_seek_to_key(block.clone(), &key_of(i));
+            assert_eq!(iter.key(), key_of(i));
+            // Seek to a key between two keys, and then scan to the end.
+            let mut iter = BlockIterator::create_and_seek_to_key(
+                block.clone(),
+                format!("key_{:03}", i * 5 + 1).as_bytes(),
+            );
+            for j in i + 1..num_of_keys() {
+                assert_eq!(iter.key(), key_of(j));
+                assert_eq!(iter.value(), value_of(j));
+                iter.next();
+            }
+            assert!(!iter.is_valid());
+        }
+        let iter = BlockIterator::create_and_seek_to_key(block, b"a");
+        assert_eq!(iter.key(), key_of(0));
+    }
+}
+
+#[test]
+fn test_block_keys_with_different_prefixes() {
+    let keys: Vec<&[u8]> = vec![
+        b"a",
+        b"aa",
+        b"aaa",
+        b"ab",
+        b"abc",
+        b"b",
+        b"tenant1/t/r1",
+        b"tenant1/t/r2",
+        b"tenant2",
+    ];
+    let mut builder = BlockBuilder::new(10000).with_restart_interval(4);
+    for key in &keys {
+        assert!(builder.add(key, key));
+    }
+    let block = Arc::new(Block::decode(&builder.build().encode()));
+    let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+    for key in &keys {
+        assert_eq!(iter.key(), *key);
+        assert_eq!(iter.value(), *key);
+        iter.next();
+    }
+    assert!(!iter.is_valid());
+    let iter = BlockIterator::create_and_seek_to_key(block.clone(), b"abb");
+    assert_eq!(iter.key(), b"abc");
+    let iter = BlockIterator::create_and_seek_to_key(block.clone(), b"tenant1/t/r15");
+    assert_eq!(iter.key(), b"tenant1/t/r2");
+    let iter = BlockIterator::create_and_seek_to_key(block, b"z");
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_block_oversized_entry() {
+    let value = vec![b'v'; 100000];
+    let mut builder = BlockBuilder::new(4096);
+    assert!(builder.add(b"key1", &value));
+    assert!(!builder.add(b"key2", b"value2"));
+    let block = Arc::new(Block::decode(&builder.build().encode()));
+    let iter = BlockIterator::create_and_seek_to_first(block);
+    assert_eq!(iter.key(), b"key1");
+    assert_eq!(iter.value(), &value[..]);
+}
+
+#[test]
+fn test_block_decode_v0() {
+    // Two restart points: `key1` and `key12` share `key1`, `key2` starts a new run.
+    let mut data = Vec::new();
+    let mut restarts = Vec::new();
+    for (shared, key, value, restart) in [
+        (0, &b"key1"[..], &b"value1"[..], true),
+        (4, b"2", b"", false),
+        (0, b"key2", b"value2", true),
+    ] {
+        if restart {
+            restarts.push(data.len() as u16);
+        }
+        data.put_u16(shared);
+        data.put_u16(key.len() as u16);
+        data.put_slice(key);
+        data.put_u8(ValueKind::Put.encode());
+        data.put_u16(value.len() as u16);
+        data.put_slice(value);
+    }
+    for restart in &restarts {
+        data.put_u16(*restart);
+    }
+    data.put_u16(restarts.len() as u16);
+    let block = Arc::new(Block::decode_v0(&data));
+    assert_eq!(block.restarts.len(), 2);
+    let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+    for (key, value) in [
+        (&b"key1"[..], &b"value1"[..]),
+        (b"key12", b""),
+        (b"key2", b"value2"),
+    ] {
+        assert_eq!(iter.key(), key);
+        assert_eq!(iter.seq(), 0);
+        assert_eq!(iter.value(), value);
+        iter.next();
+    }
+    assert!(!iter.is_valid());
+    let iter = BlockIterator::create_and_seek_to_key(block, b"key11");
+    assert_eq!(iter.key(), b"key12");
+}
+
+#[test]
+fn test_block_seek_to_newest_version() {
+    for restart_interval in [1, 2, 3, 16] {
+        let mut builder = BlockBuilder::new(10000).with_restart_interval(restart_interval);
+        assert!(builder.add_entry(b"a", 1, ValueKind::Put, b"a1"));
+        for seq in (2..=10).rev() {
+            assert!(builder.add_entry(b"b", seq, ValueKind::Put, format!("b{}", seq).as_bytes()));
+        }
+        assert!(builder.add_entry(b"c", 11, ValueKind::Delete, b""));
+        let block = Arc::new(Block::decode(&builder.build().encode()));
+        let mut iter = BlockIterator::create_and_seek_to_key(block.clone(), b"b");
+        for seq in (2..=10).rev() {
+            assert_eq!(iter.key(), b"b");
+            assert_eq!(iter.seq(), seq);
+            assert_eq!(iter.value(), format!("b{}", seq).as_bytes());
+            iter.next();
+        }
+        assert_eq!(iter.key(), b"c");
+        assert_eq!(iter.seq(), 11);
+        assert_eq!(iter.kind(), ValueKind::Delete);
+        let iter = BlockIterator::create_and_seek_to_key(block, b"c");
+        assert_eq!(iter.seq(), 11);
+    }
+}
@@ -0,0 +1,8 @@
+use super::crc32;
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    assert_ne!(crc32(b"123456789"), crc32(b"123456788"));
+}
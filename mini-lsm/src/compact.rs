@@ -0,0 +1,541 @@
+mod fifo;
+mod filter;
+mod leveled;
+mod range;
+mod tiered;
+
+use std::collections::HashSet;
+use std::ops::Bound;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+pub use fifo::{FifoCompactionController, FifoCompactionOptions, FifoCompactionTask};
+pub use filter::{CompactionDecision, CompactionFilter, TtlCompactionFilter};
+pub use leveled::{LeveledCompactionController, LeveledCompactionOptions, LeveledCompactionTask};
+pub use range::RangeCompactionTask;
+pub use tiered::{TieredCompactionController, TieredCompactionOptions, TieredCompactionTask};
+
+use crate::iterators::concat_iterator::SstConcatIterator;
+use crate::iterators::merge_iterator::MergeIterator;
+use crate::iterators::two_merge_iterator::TwoMergeIterator;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageCore, LsmStorageInner, BACKGROUND_CHECK_INTERVAL};
+use crate::manifest::ManifestRecord;
+use crate::merge_operator::resolve_operands;
+use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+use crate::value::ValueKind;
+
+/// How SSTs are compacted.
+#[derive(Debug, Clone)]
+pub enum CompactionOptions {
+    /// Keep each level sorted, and push data down level by level. Reads are cheap, but every
+    /// write is rewritten once per level.
+    Leveled(LeveledCompactionOptions),
+    /// Keep tiers of similar size, and merge them only when they pile up. Writes are cheap, but
+    /// reads have to check every tier.
+    Tiered(TieredCompactionOptions),
+    /// Keep all SSTs in L0 and delete the oldest ones once there is too much data. Keys are
+    /// expected to be written once, as overwritten and deleted keys are never cleaned up.
+    Fifo(FifoCompactionOptions),
+}
+
+impl Default for CompactionOptions {
+    fn default() -> Self {
+        CompactionOptions::Leveled(LeveledCompactionOptions::default())
+    }
+}
+
+/// A compaction picked by a [`CompactionController`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompactionTask {
+    Leveled(LeveledCompactionTask),
+    Tiered(TieredCompactionTask),
+    Fifo(FifoCompactionTask),
+    Range(RangeCompactionTask),
+}
+
+impl CompactionTask {
+    /// Get the ids of the SSTs the compaction replaces.
+    fn input_sst_ids(&self) -> Vec<usize> {
+        match self {
+            CompactionTask::Leveled(task) => task
+                .upper_level_sst_ids
+                .iter()
+                .chain(task.lower_level_sst_ids.iter())
+                .copied()
+                .collect(),
+            CompactionTask::Tiered(task) => task
+                .l0_sst_ids
+                .iter()
+                .chain(task.tiers.iter().flat_map(|(_, ids)| ids))
+                .copied()
+                .collect(),
+            CompactionTask::Fifo(task) => task.sst_ids.clone(),
+            CompactionTask::Range(task) => task
+                .l0_sst_ids
+                .iter()
+                .chain(task.level_sst_ids.iter().flat_map(|(_, ids)| ids))
+                .copied()
+                .collect(),
+        }
+    }
+
+    /// Get the level the output of the compaction is put into.
+    fn output_level(&self) -> usize {
+        match self {
+            CompactionTask::Leveled(task) => task.lower_level,
+            CompactionTask::Tiered(task) => task.output_level,
+            // FIFO compaction has no output.
+            CompactionTask::Fifo(_) => 0,
+            CompactionTask::Range(task) => task.output_level,
+        }
+    }
+}
+
+/// Picks compactions of one of the [`CompactionOptions`].
+pub enum CompactionController {
+    Leveled(LeveledCompactionController),
+    Tiered(TieredCompactionController),
+    Fifo(FifoCompactionController),
+}
+
+impl CompactionController {
+    pub fn new(options: CompactionOptions) -> Self {
+        match options {
+            CompactionOptions::Leveled(options) => {
+                CompactionController::Leveled(LeveledCompactionController::new(options))
+            }
+            CompactionOptions::Tiered(options) => {
+                CompactionController::Tiered(TieredCompactionController::new(options))
+            }
+            CompactionOptions::Fifo(options) => {
+                CompactionController::Fifo(FifoCompactionController::new(options))
+            }
+        }
+    }
+
+    /// Get the number of levels below L0.
+    pub fn num_levels(&self) -> usize {
+        match self {
+            CompactionController::Leveled(controller) => controller.num_levels(),
+            CompactionController::Tiered(controller) => controller.num_levels(),
+            CompactionController::Fifo(_) => 0,
+        }
+    }
+
+    /// Estimate how many bytes have to be compacted until no more compaction is needed.
+    pub fn estimate_pending_compaction_bytes(&self, snapshot: &LsmStorageInner) -> u64 {
+        match self {
+            CompactionController::Leveled(controller) => {
+                controller.estimate_pending_compaction_bytes(snapshot)
+            }
+            CompactionController::Tiered(controller) => {
+                controller.estimate_pending_compaction_bytes(snapshot)
+            }
+            // Deleting SSTs takes no time.
+            CompactionController::Fifo(_) => 0,
+        }
+    }
+
+    /// Pick the next compaction to run, if any.
+    pub fn generate_task(&self, snapshot: &LsmStorageInner) -> Option<CompactionTask> {
+        match self {
+            CompactionController::Leveled(controller) => controller
+                .generate_task(snapshot)
+                .map(CompactionTask::Leveled),
+            CompactionController::Tiered(controller) => controller
+                .generate_task(snapshot)
+                .map(CompactionTask::Tiered),
+            CompactionController::Fifo(controller) => {
+                controller.generate_task(snapshot).map(CompactionTask::Fifo)
+            }
+        }
+    }
+
+    /// Replace the SSTs compacted by `task` with `output`. Returns the ids of the removed SSTs.
+    pub fn apply_compaction_result(
+        &self,
+        snapshot: &mut LsmStorageInner,
+        task: &CompactionTask,
+        output: Vec<Arc<SsTable>>,
+    ) -> Vec<usize> {
+        match (self, task) {
+            (CompactionController::Leveled(controller), CompactionTask::Leveled(task)) => {
+                controller.apply_compaction_result(snapshot, task, output)
+            }
+            (CompactionController::Tiered(controller), CompactionTask::Tiered(task)) => {
+                controller.apply_compaction_result(snapshot, task, output)
+            }
+            (CompactionController::Fifo(controller), CompactionTask::Fifo(task)) => {
+                controller.apply_compaction_result(snapshot, task)
+            }
+            (_, CompactionTask::Range(task)) => task.apply_compaction_result(snapshot, output),
+            _ => unreachable!("the task was not generated by this controller"),
+        }
+    }
+}
+
+/// Replace the SSTs with the ids `input_ids` with `output`, which is added to the level
+/// `output_level` and sorted by key range with the SSTs left there. Returns the ids of the removed
+/// SSTs.
+fn replace_compacted_ssts(
+    snapshot: &mut LsmStorageInner,
+    input_ids: Vec<usize>,
+    output_level: usize,
+    output: Vec<Arc<SsTable>>,
+) -> Vec<usize> {
+    let ids: HashSet<usize> = input_ids.iter().copied().collect();
+    // New SSTs may have been flushed to L0 while compacting, so only remove the compacted ones.
+    snapshot
+        .l0_sstables
+        .retain(|table| !ids.contains(&table.id()));
+    for level in snapshot.levels.iter_mut() {
+        level.retain(|table| !ids.contains(&table.id()));
+    }
+    let output_level = &mut snapshot.levels[output_level - 1];
+    output_level.extend(output);
+    output_level.sort_by(|a, b| a.first_key().cmp(b.first_key()));
+    input_ids
+}
+
+impl LsmStorageCore {
+    /// Run compactions until no level is over its limit.
+    pub fn compact(&self) -> Result<()> {
+        let _compaction_lock = self.compaction_lock.lock();
+        loop {
+            let snapshot = self.inner.read().clone();
+            let task = match self.compaction_controller.generate_task(&snapshot) {
+                Some(task) => task,
+                None => return Ok(()),
+            };
+            self.run_compaction_task(&task)?;
+            self.notify_background_work_done();
+        }
+    }
+
+    /// Merge all SSTs that overlap a key range into the last level, after flushing the memtables
+    /// if they have keys in the range.
+    pub fn compact_range(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<()> {
+        if let CompactionController::Fifo(_) = self.compaction_controller {
+            bail!("compact_range is not supported with FIFO compaction");
+        }
+        let snapshot = self.inner.read().clone();
+        let in_memtables = std::iter::once(&snapshot.memtable)
+            .chain(snapshot.imm_memtables.iter())
+            .any(|memtable| memtable.scan(lower, upper).is_valid());
+        if in_memtables {
+            self.sync()?;
+        }
+
+        let _compaction_lock = self.compaction_lock.lock();
+        let snapshot = self.inner.read().clone();
+        if let Some(task) = RangeCompactionTask::generate(&snapshot, lower, upper) {
+            self.run_compaction_task(&CompactionTask::Range(task))?;
+            self.notify_background_work_done();
+        }
+        Ok(())
+    }
+
+    pub(crate) fn spawn_compaction_thread(
+        self: &Arc<Self>,
+        rx: Receiver<()>,
+    ) -> Result<JoinHandle<()>> {
+        let this = self.clone();
+        let handle = std::thread::Builder::new()
+            .name("mini-lsm-compaction".to_string())
+            .spawn(move || loop {
+                match rx.recv_timeout(BACKGROUND_CHECK_INTERVAL) {
+                    Ok(()) | Err(RecvTimeoutError::Timeout) => {
+                        if let Err(e) = this.compact() {
+                            this.set_background_error(e);
+                        }
+                    }
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            })?;
+        Ok(handle)
+    }
+
+    fn find_ssts(tables: &[Arc<SsTable>], ids: &[usize]) -> Vec<Arc<SsTable>> {
+        tables
+            .iter()
+            .filter(|table| ids.contains(&table.id()))
+            .cloned()
+            .collect()
+    }
+
+    fn run_compaction_task(&self, task: &CompactionTask) -> Result<()> {
+        let snapshot = self.inner.read().clone();
+        // Read the snapshots after the state, so that a snapshot taken later can see the newest
+        // version of every key in the state.
+        let snapshot_seqs = self.snapshots.seqs();
+        let output = match task {
+            CompactionTask::Leveled(task) => {
+                self.compact_leveled(&snapshot, task, &snapshot_seqs)?
+            }
+            CompactionTask::Tiered(task) => self.compact_sorted_runs(
+                &snapshot,
+                &task.l0_sst_ids,
+                &task.tiers,
+                task.is_bottom_tier_included,
+                &snapshot_seqs,
+            )?,
+            CompactionTask::Fifo(_) => Vec::new(),
+            // Nothing else overlaps the SSTs of the task, so nothing is older than them.
+            CompactionTask::Range(task) => self.compact_sorted_runs(
+                &snapshot,
+                &task.l0_sst_ids,
+                &task.level_sst_ids,
+                true,
+                &snapshot_seqs,
+            )?,
+        };
+
+        let removed_ids = {
+            let _state_lock = self.state_lock.lock();
+            let input_ids = task.input_sst_ids();
+            let mut records = Vec::with_capacity(input_ids.len() + output.len() + 1);
+            for id in input_ids {
+                records.push(ManifestRecord::RemoveSst { id });
+            }
+            for table in &output {
+                records.push(ManifestRecord::AddSst {
+                    level: task.output_level(),
+                    id: table.id(),
+                });
+            }
+            records.push(ManifestRecord::NextSstId(
+                self.next_sst_id.load(Ordering::SeqCst),
+            ));
+            self.manifest.add_records(&records)?;
+
+            let removed_ids = {
+                let mut guard = self.inner.write();
+                let mut snapshot = guard.as_ref().clone();
+                let removed_ids =
+                    self.compaction_controller
+                        .apply_compaction_result(&mut snapshot, task, output);
+                *guard = Arc::new(snapshot);
+                removed_ids
+            };
+            self.maybe_rewrite_manifest()?;
+            removed_ids
+        };
+
+        // Readers holding an old snapshot keep the files open, so they can still read them after
+        // they are removed.
+        for id in removed_ids {
+            std::fs::remove_file(self.path_of_sst(id))?;
+        }
+        Ok(())
+    }
+
+    fn compact_leveled(
+        &self,
+        snapshot: &LsmStorageInner,
+        task: &LeveledCompactionTask,
+        snapshot_seqs: &[u64],
+    ) -> Result<Vec<Arc<SsTable>>> {
+        let lower_ssts = Self::find_ssts(
+            &snapshot.levels[task.lower_level - 1],
+            &task.lower_level_sst_ids,
+        );
+        let lower_iter = SstConcatIterator::create_and_seek_to_first(lower_ssts)?;
+        match task.upper_level {
+            None => {
+                // L0 SSTs overlap, so merge them with the newest one first.
+                let mut iters = Vec::with_capacity(task.upper_level_sst_ids.len());
+                for table in snapshot.l0_sstables.iter().rev() {
+                    if task.upper_level_sst_ids.contains(&table.id()) {
+                        iters.push(Box::new(SsTableIterator::create_and_seek_to_first(
+                            table.clone(),
+                        )?));
+                    }
+                }
+                let iter = TwoMergeIterator::create(MergeIterator::create(iters), lower_iter)?;
+                self.compact_generate_sst_from_iter(
+                    iter,
+                    task.is_lower_level_bottom_level,
+                    snapshot_seqs,
+                )
+            }
+            Some(level) => {
+                let upper_ssts =
+                    Self::find_ssts(&snapshot.levels[level - 1], &task.upper_level_sst_ids);
+                let upper_iter = SstConcatIterator::create_and_seek_to_first(upper_ssts)?;
+                let iter = TwoMergeIterator::create(upper_iter, lower_iter)?;
+                self.compact_generate_sst_from_iter(
+                    iter,
+                    task.is_lower_level_bottom_level,
+                    snapshot_seqs,
+                )
+            }
+        }
+    }
+
+    /// Merge the SSTs `l0_sst_ids` and the SSTs `ids` of each level in `levels`.
+    fn compact_sorted_runs(
+        &self,
+        snapshot: &LsmStorageInner,
+        l0_sst_ids: &[usize],
+        levels: &[(usize, Vec<usize>)],
+        compact_to_bottom_level: bool,
+        snapshot_seqs: &[u64],
+    ) -> Result<Vec<Arc<SsTable>>> {
+        // Every L0 SST and every level is a sorted run. They are merged from the newest to the
+        // oldest.
+        let mut iters = Vec::with_capacity(l0_sst_ids.len() + levels.len());
+        for table in snapshot.l0_sstables.iter().rev() {
+            if l0_sst_ids.contains(&table.id()) {
+                iters.push(Box::new(SstConcatIterator::create_and_seek_to_first(
+                    vec![table.clone()],
+                )?));
+            }
+        }
+        for (level, ids) in levels {
+            let ssts = Self::find_ssts(&snapshot.levels[level - 1], ids);
+            iters.push(Box::new(SstConcatIterator::create_and_seek_to_first(ssts)?));
+        }
+        self.compact_generate_sst_from_iter(
+            MergeIterator::create(iters),
+            compact_to_bottom_level,
+            snapshot_seqs,
+        )
+    }
+
+    /// Write the entries of `iter` into new SSTs of about `target_sst_size` bytes each.
+    ///
+    /// The sequence numbers of the live snapshots split the versions of a key into stripes. A
+    /// reader only sees the newest version of a key in a stripe, so the older versions in the same
+    /// stripe are dropped. A delete in the oldest stripe is dropped too if there is no older data
+    /// it could hide. Merge operands are combined with the version they apply to if it is in the
+    /// same stripe, or if there is no older data. If the merge operator fails on them, they are
+    /// kept as they are, so that only the reads of the key fail. The newest version of a key is
+    /// passed to the compaction filter, unless a snapshot can see it.
+    fn compact_generate_sst_from_iter(
+        &self,
+        mut iter: impl StorageIterator,
+        compact_to_bottom_level: bool,
+        snapshot_seqs: &[u64],
+    ) -> Result<Vec<Arc<SsTable>>> {
+        // The stripe of a version is the number of snapshots that cannot see it.
+        let stripe_of = |seq: u64| snapshot_seqs.partition_point(|x| *x < seq);
+        let mut builder = self.new_sst_builder();
+        let mut output = Vec::new();
+        let mut last_key = Vec::new();
+        let mut last_stripe = None;
+        while iter.is_valid() {
+            if iter.key() != last_key {
+                // Only start a new SST between two keys, so that all versions of a key are in the
+                // same SST.
+                if builder.estimated_size() >= self.options.target_sst_size && !builder.is_empty() {
+                    let builder = std::mem::replace(&mut builder, self.new_sst_builder());
+                    output.push(self.build_sst(builder)?);
+                }
+                last_key.clear();
+                last_key.extend_from_slice(iter.key());
+                last_stripe = None;
+            }
+            let stripe = stripe_of(iter.seq());
+            if last_stripe == Some(stripe) {
+                iter.next()?;
+                continue;
+            }
+            let is_newest = last_stripe.is_none();
+            last_stripe = Some(stripe);
+            let seq = iter.seq();
+            let mut merged = None;
+            if iter.kind() == ValueKind::Merge {
+                // Collect the operands in the stripe, and the version they apply to.
+                let mut operands = Vec::new();
+                let mut existing = None;
+                while iter.is_valid() && iter.key() == last_key && stripe_of(iter.seq()) == stripe {
+                    match iter.kind() {
+                        ValueKind::Merge => {
+                            operands.push((iter.seq(), Bytes::copy_from_slice(iter.value())))
+                        }
+                        ValueKind::Put => {
+                            existing = Some(Some(Bytes::copy_from_slice(iter.value())));
+                            break;
+                        }
+                        ValueKind::Delete => {
+                            existing = Some(None);
+                            break;
+                        }
+                    }
+                    iter.next()?;
+                }
+                let is_key_done = !iter.is_valid() || iter.key() != last_key;
+                if existing.is_none() && !(compact_to_bottom_level && is_key_done) {
+                    // The version the operands apply to may be in another SST.
+                    for (seq, operand) in operands {
+                        builder.add_entry(&last_key, seq, ValueKind::Merge, &operand);
+                    }
+                    continue;
+                }
+                let resolved = resolve_operands(
+                    self.options.merge_operator.as_ref(),
+                    &last_key,
+                    existing.clone().flatten().as_deref(),
+                    operands
+                        .iter()
+                        .map(|(_, operand)| operand.clone())
+                        .collect(),
+                );
+                match resolved {
+                    Ok(value) => merged = Some(value),
+                    Err(_) => {
+                        // Keep the operands and the version they apply to, so that a bad operand
+                        // fails the reads of its key rather than every compaction.
+                        for (seq, operand) in operands {
+                            builder.add_entry(&last_key, seq, ValueKind::Merge, &operand);
+                        }
+                        if existing.is_some() {
+                            builder.add_entry(&last_key, iter.seq(), iter.kind(), iter.value());
+                            iter.next()?;
+                        }
+                        continue;
+                    }
+                }
+            }
+            let (kind, value) = match &merged {
+                Some(value) => (ValueKind::Put, &value[..]),
+                None => (iter.kind(), iter.value()),
+            };
+            let decision = match &self.options.compaction_filter {
+                Some(filter)
+                    if is_newest && stripe == snapshot_seqs.len() && kind == ValueKind::Put =>
+                {
+                    filter.filter(&last_key, value)
+                }
+                _ => CompactionDecision::Keep,
+            };
+            let (kind, value) = match &decision {
+                CompactionDecision::Keep => (kind, value),
+                // The older versions may be in other SSTs, so a delete has to hide them.
+                CompactionDecision::Remove => (ValueKind::Delete, &[][..]),
+                CompactionDecision::ChangeValue(value) => (ValueKind::Put, &value[..]),
+            };
+            if !(compact_to_bottom_level && stripe == 0 && kind == ValueKind::Delete) {
+                builder.add_entry(&last_key, seq, kind, value);
+            }
+        }
+        if !builder.is_empty() {
+            output.push(self.build_sst(builder)?);
+        }
+        Ok(output)
+    }
+
+    fn build_sst(&self, builder: SsTableBuilder) -> Result<Arc<SsTable>> {
+        let id = self.next_sst_id.fetch_add(1, Ordering::SeqCst);
+        Ok(Arc::new(builder.build(
+            id,
+            Some(self.block_cache.clone()),
+            self.path_of_sst(id),
+        )?))
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::lsm_storage::LsmStorageInner;
+use crate::table::SsTable;
+
+/// Options of FIFO compaction, for data that is never updated, such as logs and metrics.
+///
+/// All SSTs stay in L0 in the order they were flushed, and the oldest ones are deleted once the
+/// data grows too large or too old. Data is never rewritten.
+#[derive(Debug, Clone)]
+pub struct FifoCompactionOptions {
+    /// The oldest SSTs are deleted while the total size of the SSTs is larger than this, in bytes.
+    pub max_table_files_size: u64,
+    /// SSTs built longer ago than this are deleted. `None` keeps SSTs regardless of their age.
+    pub ttl: Option<Duration>,
+}
+
+impl Default for FifoCompactionOptions {
+    fn default() -> Self {
+        Self {
+            max_table_files_size: 1 << 30,
+            ttl: None,
+        }
+    }
+}
+
+/// Delete `sst_ids`, which are the oldest SSTs in L0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FifoCompactionTask {
+    pub sst_ids: Vec<usize>,
+}
+
+pub struct FifoCompactionController {
+    options: FifoCompactionOptions,
+}
+
+impl FifoCompactionController {
+    pub fn new(options: FifoCompactionOptions) -> Self {
+        Self { options }
+    }
+
+    /// Pick the oldest SSTs that are over the size limit or expired, if any.
+    pub fn generate_task(&self, snapshot: &LsmStorageInner) -> Option<FifoCompactionTask> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let is_expired = |table: &SsTable| match self.options.ttl {
+            Some(ttl) => now.saturating_sub(table.properties().creation_time) >= ttl.as_secs(),
+            None => false,
+        };
+        let mut total_size: u64 = snapshot
+            .l0_sstables
+            .iter()
+            .map(|table| table.table_size())
+            .sum();
+        let mut sst_ids = Vec::new();
+        for table in &snapshot.l0_sstables {
+            if total_size <= self.options.max_table_files_size && !is_expired(table) {
+                break;
+            }
+            total_size -= table.table_size();
+            sst_ids.push(table.id());
+        }
+        if sst_ids.is_empty() {
+            return None;
+        }
+        Some(FifoCompactionTask { sst_ids })
+    }
+
+    /// Remove the SSTs deleted by `task`. Returns their ids.
+    pub fn apply_compaction_result(
+        &self,
+        snapshot: &mut LsmStorageInner,
+        task: &FifoCompactionTask,
+    ) -> Vec<usize> {
+        let ids: HashSet<usize> = task.sst_ids.iter().copied().collect();
+        snapshot
+            .l0_sstables
+            .retain(|table| !ids.contains(&table.id()));
+        task.sst_ids.clone()
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::{Buf, BufMut, Bytes};
+
+/// What a [`CompactionFilter`] does with an entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompactionDecision {
+    /// Keep the entry as it is.
+    Keep,
+    /// Remove the key, as if it were deleted.
+    Remove,
+    /// Replace the value of the entry.
+    ChangeValue(Bytes),
+}
+
+/// A hook that drops or rewrites entries while they are compacted, set through
+/// `LsmStorageOptions::compaction_filter`.
+///
+/// The filter is called with the newest version of each key that is not visible to any snapshot.
+/// Older versions and deletes are never passed to it. Entries are only filtered when they are
+/// compacted, so readers may still see them before that.
+pub trait CompactionFilter: std::fmt::Debug + Send + Sync {
+    fn filter(&self, key: &[u8], value: &[u8]) -> CompactionDecision;
+}
+
+const SIZEOF_U64: usize = std::mem::size_of::<u64>();
+
+/// Removes entries whose time to live has passed.
+///
+/// The values must be encoded with [`TtlCompactionFilter::encode_value`], which appends the time
+/// at which the entry expires. Values too short to hold the time are kept.
+#[derive(Debug, Default)]
+pub struct TtlCompactionFilter;
+
+impl TtlCompactionFilter {
+    /// Encode a value that expires at `expire_at`, in seconds since the Unix epoch.
+    pub fn encode_value(value: &[u8], expire_at: u64) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(value.len() + SIZEOF_U64);
+        buf.put_slice(value);
+        buf.put_u64(expire_at);
+        buf
+    }
+
+    /// Split a value encoded with [`TtlCompactionFilter::encode_value`] into the value and the time
+    /// it expires at.
+    pub fn decode_value(value: &[u8]) -> Option<(&[u8], u64)> {
+        if value.len() < SIZEOF_U64 {
+            return None;
+        }
+        let (value, mut expire_at) = value.split_at(value.len() - SIZEOF_U64);
+        Some((value, expire_at.get_u64()))
+    }
+}
+
+impl CompactionFilter for TtlCompactionFilter {
+    fn filter(&self, _key: &[u8], value: &[u8]) -> CompactionDecision {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        match Self::decode_value(value) {
+            Some((_, expire_at)) if expire_at <= now => CompactionDecision::Remove,
+            _ => CompactionDecision::Keep,
+        }
+    }
+}
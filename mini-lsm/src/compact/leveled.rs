@@ -0,0 +1,176 @@
+use std::sync::Arc;
+
+use super::replace_compacted_ssts;
+use crate::lsm_storage::LsmStorageInner;
+use crate::table::SsTable;
+
+/// Options of leveled compaction.
+#[derive(Debug, Clone)]
+pub struct LeveledCompactionOptions {
+    /// Number of L0 SSTs that triggers a compaction into L1.
+    pub level0_file_num_compaction_trigger: usize,
+    /// Number of levels below L0.
+    pub max_levels: usize,
+    /// Target size of L1 in bytes.
+    pub base_level_size: u64,
+    /// Each level is allowed to be this many times as large as the level above it.
+    pub level_size_multiplier: u64,
+}
+
+impl Default for LeveledCompactionOptions {
+    fn default() -> Self {
+        Self {
+            level0_file_num_compaction_trigger: 4,
+            max_levels: 6,
+            base_level_size: 10 << 20,
+            level_size_multiplier: 10,
+        }
+    }
+}
+
+/// Merge `upper_level_sst_ids` from `upper_level` (`None` for L0) with the overlapping
+/// `lower_level_sst_ids` from `lower_level`, and put the result into `lower_level`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeveledCompactionTask {
+    pub upper_level: Option<usize>,
+    pub upper_level_sst_ids: Vec<usize>,
+    pub lower_level: usize,
+    pub lower_level_sst_ids: Vec<usize>,
+    /// Nothing is stored below `lower_level`, so deletions can be dropped.
+    pub is_lower_level_bottom_level: bool,
+}
+
+pub struct LeveledCompactionController {
+    options: LeveledCompactionOptions,
+}
+
+impl LeveledCompactionController {
+    pub fn new(options: LeveledCompactionOptions) -> Self {
+        Self { options }
+    }
+
+    /// Get the number of levels below L0.
+    pub fn num_levels(&self) -> usize {
+        self.options.max_levels
+    }
+
+    /// Get the target size of the level `level` (starting from 1).
+    fn target_size(&self, level: usize) -> u64 {
+        let mut size = self.options.base_level_size;
+        for _ in 1..level {
+            size = size.saturating_mul(self.options.level_size_multiplier);
+        }
+        size
+    }
+
+    fn level_size(level: &[Arc<SsTable>]) -> u64 {
+        level.iter().map(|table| table.table_size()).sum()
+    }
+
+    /// Find the SSTs in `level` (starting from 1) whose key range overlaps with any of `tables`.
+    fn find_overlapping_ssts(
+        snapshot: &LsmStorageInner,
+        tables: &[Arc<SsTable>],
+        level: usize,
+    ) -> Vec<usize> {
+        let first_key = tables.iter().map(|table| table.first_key()).min().unwrap();
+        let last_key = tables.iter().map(|table| table.last_key()).max().unwrap();
+        snapshot.levels[level - 1]
+            .iter()
+            .filter(|table| table.first_key() <= last_key && table.last_key() >= first_key)
+            .map(|table| table.id())
+            .collect()
+    }
+
+    fn is_bottom_level(snapshot: &LsmStorageInner, level: usize) -> bool {
+        snapshot.levels[level..]
+            .iter()
+            .all(|level| level.is_empty())
+    }
+
+    /// Estimate how many bytes have to be compacted until no level is over its limit.
+    pub fn estimate_pending_compaction_bytes(&self, snapshot: &LsmStorageInner) -> u64 {
+        let mut pending = 0;
+        if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger {
+            pending += Self::level_size(&snapshot.l0_sstables);
+        }
+        // Data compacted into a level has to be compacted further down if that level overflows.
+        let mut incoming = pending;
+        for level in 1..snapshot.levels.len() {
+            let size = Self::level_size(&snapshot.levels[level - 1]) + incoming;
+            incoming = size.saturating_sub(self.target_size(level));
+            pending += incoming;
+        }
+        pending
+    }
+
+    /// Pick the next compaction to run, if any level is over its limit.
+    pub fn generate_task(&self, snapshot: &LsmStorageInner) -> Option<LeveledCompactionTask> {
+        if snapshot.levels.is_empty() {
+            return None;
+        }
+
+        // Flush L0 into L1 first, as L0 SSTs overlap and every read has to check all of them.
+        if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger {
+            return Some(LeveledCompactionTask {
+                upper_level: None,
+                upper_level_sst_ids: snapshot.l0_sstables.iter().map(|x| x.id()).collect(),
+                lower_level: 1,
+                lower_level_sst_ids: Self::find_overlapping_ssts(
+                    snapshot,
+                    &snapshot.l0_sstables,
+                    1,
+                ),
+                is_lower_level_bottom_level: Self::is_bottom_level(snapshot, 1),
+            });
+        }
+
+        // Then compact the level that exceeds its target size the most. The last level has no
+        // level to compact into.
+        let mut max_score = 1.0;
+        let mut max_level = None;
+        for level in 1..snapshot.levels.len() {
+            let score = Self::level_size(&snapshot.levels[level - 1]) as f64
+                / self.target_size(level) as f64;
+            if score > max_score {
+                max_score = score;
+                max_level = Some(level);
+            }
+        }
+        let level = max_level?;
+
+        // Compact the oldest SST of the level, so that every key range gets its turn.
+        let table = snapshot.levels[level - 1]
+            .iter()
+            .min_by_key(|table| table.id())
+            .unwrap();
+        Some(LeveledCompactionTask {
+            upper_level: Some(level),
+            upper_level_sst_ids: vec![table.id()],
+            lower_level: level + 1,
+            lower_level_sst_ids: Self::find_overlapping_ssts(
+                snapshot,
+                std::slice::from_ref(table),
+                level + 1,
+            ),
+            is_lower_level_bottom_level: Self::is_bottom_level(snapshot, level + 1),
+        })
+    }
+
+    /// Replace the SSTs compacted by `task` with `output` in the lower level. Returns the ids of the
+    /// removed SSTs.
+    pub fn apply_compaction_result(
+        &self,
+        snapshot: &mut LsmStorageInner,
+        task: &LeveledCompactionTask,
+        output: Vec<Arc<SsTable>>,
+    ) -> Vec<usize> {
+        let input_ids = task
+            .upper_level_sst_ids
+            .iter()
+            .chain(&task.lower_level_sst_ids)
+            .copied()
+            .collect();
+        replace_compacted_ssts(snapshot, input_ids, task.lower_level, output)
+    }
+}
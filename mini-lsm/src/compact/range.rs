@@ -0,0 +1,94 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use super::replace_compacted_ssts;
+use crate::lsm_storage::{range_overlap, LsmStorageInner};
+use crate::table::SsTable;
+
+/// Merge all SSTs that overlap a key range into the last level, as requested by
+/// `LsmStorage::compact_range`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeCompactionTask {
+    pub l0_sst_ids: Vec<usize>,
+    /// The levels with SSTs to merge, and those SSTs.
+    pub level_sst_ids: Vec<(usize, Vec<usize>)>,
+    pub output_level: usize,
+}
+
+impl RangeCompactionTask {
+    /// Pick the SSTs that overlap the range between `lower` and `upper`, and the SSTs that
+    /// overlap those, until no other SST overlaps the merged data. Then nothing else can be in
+    /// the way of the output in the last level, and no older data can be below it. Returns `None`
+    /// if no SST is in the range, or if there are no levels to put the output in.
+    pub(crate) fn generate(
+        snapshot: &LsmStorageInner,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Option<Self> {
+        if snapshot.levels.is_empty() {
+            return None;
+        }
+        let select = |lower: Bound<&[u8]>, upper: Bound<&[u8]>| {
+            let l0_sstables: Vec<_> = snapshot
+                .l0_sstables
+                .iter()
+                .filter(|table| range_overlap(lower, upper, table))
+                .cloned()
+                .collect();
+            let levels: Vec<Vec<_>> = snapshot
+                .levels
+                .iter()
+                .map(|level| {
+                    level
+                        .iter()
+                        .filter(|table| range_overlap(lower, upper, table))
+                        .cloned()
+                        .collect()
+                })
+                .collect();
+            (l0_sstables, levels)
+        };
+        let (mut l0_sstables, mut levels) = select(lower, upper);
+        loop {
+            let selected = || l0_sstables.iter().chain(levels.iter().flatten());
+            let num_selected = selected().count();
+            let first_key = selected().map(|table| table.first_key()).min()?.clone();
+            let last_key = selected().map(|table| table.last_key()).max()?.clone();
+            let (new_l0_sstables, new_levels) =
+                select(Bound::Included(&first_key), Bound::Included(&last_key));
+            l0_sstables = new_l0_sstables;
+            levels = new_levels;
+            if l0_sstables.len() + levels.iter().map(Vec::len).sum::<usize>() == num_selected {
+                break;
+            }
+        }
+
+        let ids = |tables: &[Arc<SsTable>]| tables.iter().map(|table| table.id()).collect();
+        Some(Self {
+            l0_sst_ids: ids(&l0_sstables),
+            level_sst_ids: levels
+                .iter()
+                .enumerate()
+                .filter(|(_, level)| !level.is_empty())
+                .map(|(idx, level)| (idx + 1, ids(level)))
+                .collect(),
+            output_level: snapshot.levels.len(),
+        })
+    }
+
+    /// Replace the SSTs compacted by the task with `output` in the output level. Returns the ids of
+    /// the removed SSTs.
+    pub(crate) fn apply_compaction_result(
+        &self,
+        snapshot: &mut LsmStorageInner,
+        output: Vec<Arc<SsTable>>,
+    ) -> Vec<usize> {
+        let input_ids = self
+            .l0_sst_ids
+            .iter()
+            .chain(self.level_sst_ids.iter().flat_map(|(_, ids)| ids))
+            .copied()
+            .collect();
+        replace_compacted_ssts(snapshot, input_ids, self.output_level, output)
+    }
+}
@@ -0,0 +1,172 @@
+use std::sync::Arc;
+
+use super::replace_compacted_ssts;
+use crate::lsm_storage::LsmStorageInner;
+use crate::table::SsTable;
+
+/// Options of tiered (universal) compaction.
+///
+/// Flushed SSTs stay in L0 until enough of them pile up. Then they are merged into a new tier,
+/// together with the newest tiers that are not much larger than what is merged so far. Each tier
+/// is a sorted run kept in a level, with the newest tier in the smallest level, so that data is
+/// rewritten far less often than in leveled compaction.
+#[derive(Debug, Clone)]
+pub struct TieredCompactionOptions {
+    /// Number of L0 SSTs that triggers a compaction into a new tier.
+    pub level0_file_num_compaction_trigger: usize,
+    /// Maximum number of tiers. It is also the number of levels the tiers are kept in.
+    pub num_tiers: usize,
+    /// A tier is merged with the newer data if it is at most this many percent larger than all of
+    /// that data together.
+    pub size_ratio: u64,
+    /// All tiers are merged into one once the size of the data newer than the oldest tier is this
+    /// many percent of the size of the oldest tier.
+    pub max_size_amplification_percent: u64,
+}
+
+impl Default for TieredCompactionOptions {
+    fn default() -> Self {
+        Self {
+            level0_file_num_compaction_trigger: 4,
+            num_tiers: 8,
+            size_ratio: 1,
+            max_size_amplification_percent: 200,
+        }
+    }
+}
+
+/// Merge `l0_sst_ids` with the `tiers`, which are the newest tiers, and put the result into
+/// `output_level` as a new tier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TieredCompactionTask {
+    pub l0_sst_ids: Vec<usize>,
+    /// The levels of the tiers to merge, from the newest to the oldest, and their SSTs.
+    pub tiers: Vec<(usize, Vec<usize>)>,
+    pub output_level: usize,
+    /// The oldest tier is merged, so deletions can be dropped.
+    pub is_bottom_tier_included: bool,
+}
+
+pub struct TieredCompactionController {
+    options: TieredCompactionOptions,
+}
+
+impl TieredCompactionController {
+    pub fn new(options: TieredCompactionOptions) -> Self {
+        Self { options }
+    }
+
+    /// Get the number of levels the tiers are kept in.
+    pub fn num_levels(&self) -> usize {
+        self.options.num_tiers.max(1)
+    }
+
+    fn size(tables: &[Arc<SsTable>]) -> u64 {
+        tables.iter().map(|table| table.table_size()).sum()
+    }
+
+    /// Estimate how many bytes the next compaction has to merge.
+    pub fn estimate_pending_compaction_bytes(&self, snapshot: &LsmStorageInner) -> u64 {
+        match self.generate_task(snapshot) {
+            Some(task) => {
+                Self::size(&snapshot.l0_sstables)
+                    + task
+                        .tiers
+                        .iter()
+                        .map(|(level, _)| Self::size(&snapshot.levels[level - 1]))
+                        .sum::<u64>()
+            }
+            None => 0,
+        }
+    }
+
+    /// Pick the next compaction to run, once enough SSTs are in L0.
+    pub fn generate_task(&self, snapshot: &LsmStorageInner) -> Option<TieredCompactionTask> {
+        if snapshot.l0_sstables.len() < self.options.level0_file_num_compaction_trigger
+            || snapshot.levels.is_empty()
+        {
+            return None;
+        }
+        // The levels that hold a tier, from the newest to the oldest.
+        let tiers: Vec<usize> = (1..=snapshot.levels.len())
+            .filter(|level| !snapshot.levels[level - 1].is_empty())
+            .collect();
+        let tier_size = |idx: usize| Self::size(&snapshot.levels[tiers[idx] - 1]);
+
+        // Merge everything if too much space is taken by data that may be overwritten.
+        let l0_size = Self::size(&snapshot.l0_sstables);
+        let num_merged = match tiers.split_last() {
+            Some((_, newer_tiers))
+                if (l0_size + (0..newer_tiers.len()).map(tier_size).sum::<u64>())
+                    .saturating_mul(100)
+                    >= tier_size(tiers.len() - 1)
+                        .saturating_mul(self.options.max_size_amplification_percent) =>
+            {
+                tiers.len()
+            }
+            _ => {
+                // Otherwise merge the tiers that are not much larger than the newer data.
+                let mut merged_size = l0_size;
+                let mut num_merged = 0;
+                while num_merged < tiers.len()
+                    && tier_size(num_merged).saturating_mul(100)
+                        <= merged_size.saturating_mul(100 + self.options.size_ratio)
+                {
+                    merged_size += tier_size(num_merged);
+                    num_merged += 1;
+                }
+                // Merge enough tiers to stay within `num_tiers`, and to leave a free level for
+                // the new tier in front of the remaining ones.
+                let min_merged = (tiers.len() + 1).saturating_sub(self.num_levels());
+                num_merged = num_merged.max(min_merged);
+                if num_merged == 0 && tiers.first() == Some(&1) {
+                    num_merged = 1;
+                }
+                num_merged
+            }
+        };
+
+        let output_level = match num_merged {
+            0 => tiers
+                .first()
+                .map_or(snapshot.levels.len(), |level| level - 1),
+            n => tiers[n - 1],
+        };
+        Some(TieredCompactionTask {
+            l0_sst_ids: snapshot
+                .l0_sstables
+                .iter()
+                .map(|table| table.id())
+                .collect(),
+            tiers: tiers[..num_merged]
+                .iter()
+                .map(|level| {
+                    let ids = snapshot.levels[level - 1]
+                        .iter()
+                        .map(|table| table.id())
+                        .collect();
+                    (*level, ids)
+                })
+                .collect(),
+            output_level,
+            is_bottom_tier_included: num_merged == tiers.len(),
+        })
+    }
+
+    /// Replace the tiers compacted by `task` with the tier of `output`. Returns the ids of the
+    /// removed SSTs.
+    pub fn apply_compaction_result(
+        &self,
+        snapshot: &mut LsmStorageInner,
+        task: &TieredCompactionTask,
+        output: Vec<Arc<SsTable>>,
+    ) -> Vec<usize> {
+        let input_ids = task
+            .l0_sst_ids
+            .iter()
+            .chain(task.tiers.iter().flat_map(|(_, ids)| ids))
+            .copied()
+            .collect();
+        replace_compacted_ssts(snapshot, input_ids, task.output_level, output)
+    }
+}
@@ -1,10 +1,20 @@
+pub mod concat_iterator;
 pub mod merge_iterator;
 pub mod two_merge_iterator;
 
+use crate::value::ValueKind;
+
 pub trait StorageIterator {
     /// Get the current value.
     fn value(&self) -> &[u8];
 
+    /// Get the kind of the current entry. The value of a delete is empty.
+    fn kind(&self) -> ValueKind;
+
+    /// Get the sequence number of the current entry. The versions of a key are ordered from the
+    /// newest to the oldest.
+    fn seq(&self) -> u64;
+
     /// Get the current key.
     fn key(&self) -> &[u8];
 
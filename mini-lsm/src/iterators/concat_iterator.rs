@@ -0,0 +1,130 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::StorageIterator;
+use crate::mem_table::map_bound;
+use crate::table::{SsTable, SsTableIterator};
+use crate::value::ValueKind;
+
+/// Concatenates SSTs that are sorted by key range and do not overlap, such as the SSTs of a level
+/// below L0. Only one SST is open at a time, and the next one is opened when the current one is
+/// exhausted.
+pub struct SstConcatIterator {
+    current: Option<SsTableIterator>,
+    next_sst_idx: usize,
+    sstables: Vec<Arc<SsTable>>,
+    /// The iterator is invalid after the keys past this bound.
+    upper: Bound<Bytes>,
+}
+
+impl SstConcatIterator {
+    fn check_sst_valid(sstables: &[Arc<SsTable>]) {
+        for window in sstables.windows(2) {
+            debug_assert!(
+                window[0].last_key() < window[1].first_key(),
+                "SSTs overlap or are out of order"
+            );
+        }
+    }
+
+    /// Create a new iterator and seek to the first key-value pair.
+    pub fn create_and_seek_to_first(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
+        Self::check_sst_valid(&sstables);
+        let mut iter = Self {
+            current: None,
+            next_sst_idx: 0,
+            sstables,
+            upper: Bound::Unbounded,
+        };
+        iter.move_until_valid()?;
+        Ok(iter)
+    }
+
+    /// Create a new iterator and seek to the newest version of the first key which >= `key`.
+    pub fn create_and_seek_to_key(sstables: Vec<Arc<SsTable>>, key: &[u8]) -> Result<Self> {
+        Self::check_sst_valid(&sstables);
+        let idx = sstables
+            .partition_point(|table| table.first_key() <= key)
+            .saturating_sub(1);
+        let mut iter = Self {
+            current: None,
+            next_sst_idx: idx + 1,
+            sstables,
+            upper: Bound::Unbounded,
+        };
+        if let Some(table) = iter.sstables.get(idx) {
+            iter.current = Some(SsTableIterator::create_and_seek_to_key(table.clone(), key)?);
+        }
+        iter.move_until_valid()?;
+        Ok(iter)
+    }
+
+    /// Make the iterator invalid once it moves past `upper`. The SSTs and blocks after it are not
+    /// read.
+    pub fn set_upper_bound(&mut self, upper: Bound<&[u8]>) {
+        if let Some(iter) = self.current.as_mut() {
+            iter.set_upper_bound(upper);
+        }
+        self.upper = map_bound(upper);
+    }
+
+    /// Open the following SSTs until the iterator points to a valid entry or all SSTs are used.
+    fn move_until_valid(&mut self) -> Result<()> {
+        while self.current.as_ref().map_or(true, |iter| !iter.is_valid()) {
+            match self.sstables.get(self.next_sst_idx) {
+                Some(table)
+                    if SsTableIterator::within_upper_bound(&self.upper, table.first_key()) =>
+                {
+                    let mut iter = SsTableIterator::create_and_seek_to_first(table.clone())?;
+                    iter.set_upper_bound(match &self.upper {
+                        Bound::Included(key) => Bound::Included(key),
+                        Bound::Excluded(key) => Bound::Excluded(key),
+                        Bound::Unbounded => Bound::Unbounded,
+                    });
+                    self.current = Some(iter);
+                    self.next_sst_idx += 1;
+                }
+                _ => {
+                    self.current = None;
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StorageIterator for SstConcatIterator {
+    fn key(&self) -> &[u8] {
+        self.current.as_ref().unwrap().key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.current.as_ref().unwrap().value()
+    }
+
+    fn kind(&self) -> ValueKind {
+        self.current.as_ref().unwrap().kind()
+    }
+
+    fn seq(&self) -> u64 {
+        self.current.as_ref().unwrap().seq()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.current
+            .as_ref()
+            .map(|iter| iter.is_valid())
+            .unwrap_or(false)
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if let Some(iter) = self.current.as_mut() {
+            iter.next()?;
+        }
+        self.move_until_valid()
+    }
+}
@@ -5,6 +5,8 @@ use std::collections::BinaryHeap;
 use anyhow::Result;
 
 use super::StorageIterator;
+use crate::key;
+use crate::value::ValueKind;
 
 struct HeapWrapper<I: StorageIterator>(pub usize, pub Box<I>);
 
@@ -18,7 +20,7 @@ impl<I: StorageIterator> Eq for HeapWrapper<I> {}
 
 impl<I: StorageIterator> PartialOrd for HeapWrapper<I> {
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        match self.1.key().cmp(other.1.key()) {
+        match key::compare(self.1.key(), self.1.seq(), other.1.key(), other.1.seq()) {
             cmp::Ordering::Greater => Some(cmp::Ordering::Greater),
             cmp::Ordering::Less => Some(cmp::Ordering::Less),
             cmp::Ordering::Equal => self.0.partial_cmp(&other.0),
@@ -33,8 +35,9 @@ impl<I: StorageIterator> Ord for HeapWrapper<I> {
     }
 }
 
-/// Merge multiple iterators of the same type. If the same key occurs multiple times in some
-/// iterators, perfer the one with smaller index.
+/// Merge multiple iterators of the same type. The versions of a key are produced from the newest
+/// to the oldest. If the same version occurs in several iterators, it is produced once, from the
+/// one with smaller index.
 pub struct MergeIterator<I: StorageIterator> {
     iters: BinaryHeap<HeapWrapper<I>>,
     current: Option<HeapWrapper<I>>,
@@ -85,6 +88,14 @@ impl<I: StorageIterator> StorageIterator for MergeIterator<I> {
             .value()
     }
 
+    fn kind(&self) -> ValueKind {
+        unsafe { self.current.as_ref().unwrap_unchecked() }.1.kind()
+    }
+
+    fn seq(&self) -> u64 {
+        unsafe { self.current.as_ref().unwrap_unchecked() }.1.seq()
+    }
+
     fn is_valid(&self) -> bool {
         self.current
             .as_ref()
@@ -94,13 +105,16 @@ impl<I: StorageIterator> StorageIterator for MergeIterator<I> {
 
     fn next(&mut self) -> Result<()> {
         let current = unsafe { self.current.as_mut().unwrap_unchecked() };
-        // Pop the item out of the heap if they have the same value.
+        // Pop the item out of the heap if they have the same version.
         while let Some(mut inner_iter) = self.iters.peek_mut() {
-            debug_assert!(
-                inner_iter.1.key() >= current.1.key(),
-                "heap invariant violated"
+            let ordering = key::compare(
+                inner_iter.1.key(),
+                inner_iter.1.seq(),
+                current.1.key(),
+                current.1.seq(),
             );
-            if inner_iter.1.key() == current.1.key() {
+            debug_assert!(ordering.is_ge(), "heap invariant violated");
+            if ordering.is_eq() {
                 // Case 1: an error occurred when calling `next`.
                 if let e @ Err(_) = inner_iter.1.next() {
                     PeekMut::pop(inner_iter);
@@ -2,6 +2,7 @@ use anyhow::Result;
 use bytes::Bytes;
 
 use super::StorageIterator;
+use crate::value::ValueKind;
 
 pub mod merge_iterator_test;
 pub mod two_merge_iterator_test;
@@ -9,12 +10,31 @@ pub mod two_merge_iterator_test;
 #[derive(Clone)]
 pub struct MockIterator {
     pub data: Vec<(Bytes, Bytes)>,
+    pub seqs: Vec<u64>,
     pub index: usize,
 }
 
 impl MockIterator {
     pub fn new(data: Vec<(Bytes, Bytes)>) -> Self {
-        Self { data, index: 0 }
+        let seqs = vec![0; data.len()];
+        Self {
+            data,
+            seqs,
+            index: 0,
+        }
+    }
+
+    pub fn with_seqs(data: Vec<(Bytes, u64, Bytes)>) -> Self {
+        let seqs = data.iter().map(|(_, seq, _)| *seq).collect();
+        let data = data
+            .into_iter()
+            .map(|(key, _, value)| (key, value))
+            .collect();
+        Self {
+            data,
+            seqs,
+            index: 0,
+        }
     }
 }
 
@@ -34,6 +54,14 @@ impl StorageIterator for MockIterator {
         self.data[self.index].1.as_ref()
     }
 
+    fn kind(&self) -> ValueKind {
+        ValueKind::Put
+    }
+
+    fn seq(&self) -> u64 {
+        self.seqs[self.index]
+    }
+
     fn is_valid(&self) -> bool {
         self.index < self.data.len()
     }
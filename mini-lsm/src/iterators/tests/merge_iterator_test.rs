@@ -135,3 +135,31 @@ fn test_merge_empty() {
     let iter = MergeIterator::<MockIterator>::create(vec![]);
     check_iter_result(iter, vec![]);
 }
+
+#[test]
+fn test_merge_versions() {
+    let i1 = MockIterator::with_seqs(vec![
+        (Bytes::from("a"), 5, Bytes::from("a5")),
+        (Bytes::from("b"), 2, Bytes::from("b2")),
+    ]);
+    let i2 = MockIterator::with_seqs(vec![
+        (Bytes::from("a"), 7, Bytes::from("a7")),
+        (Bytes::from("a"), 1, Bytes::from("a1")),
+        (Bytes::from("b"), 2, Bytes::from("b2 duplicate")),
+    ]);
+    let i3 = MockIterator::with_seqs(vec![(Bytes::from("b"), 3, Bytes::from("b3"))]);
+    let mut iter = MergeIterator::create(vec![Box::new(i1), Box::new(i2), Box::new(i3)]);
+    for (key, seq, value) in [
+        ("a", 7, "a7"),
+        ("a", 5, "a5"),
+        ("a", 1, "a1"),
+        ("b", 3, "b3"),
+        ("b", 2, "b2"),
+    ] {
+        assert_eq!(iter.key(), key.as_bytes());
+        assert_eq!(iter.seq(), seq);
+        assert_eq!(iter.value(), value.as_bytes());
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
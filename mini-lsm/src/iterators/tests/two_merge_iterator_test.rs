@@ -127,3 +127,29 @@ fn test_merge_5() {
     let iter = TwoMergeIterator::create(i1, i2).unwrap();
     check_iter_result(iter, vec![])
 }
+
+#[test]
+fn test_merge_versions() {
+    let i1 = MockIterator::with_seqs(vec![
+        (Bytes::from("a"), 3, Bytes::from("a3")),
+        (Bytes::from("b"), 4, Bytes::from("b4")),
+    ]);
+    let i2 = MockIterator::with_seqs(vec![
+        (Bytes::from("a"), 5, Bytes::from("a5")),
+        (Bytes::from("a"), 3, Bytes::from("a3 duplicate")),
+        (Bytes::from("b"), 1, Bytes::from("b1")),
+    ]);
+    let mut iter = TwoMergeIterator::create(i1, i2).unwrap();
+    for (key, seq, value) in [
+        ("a", 5, "a5"),
+        ("a", 3, "a3"),
+        ("b", 4, "b4"),
+        ("b", 1, "b1"),
+    ] {
+        assert_eq!(iter.key(), key.as_bytes());
+        assert_eq!(iter.seq(), seq);
+        assert_eq!(iter.value(), value.as_bytes());
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
@@ -1,9 +1,12 @@
 use anyhow::Result;
 
 use super::StorageIterator;
+use crate::key;
+use crate::value::ValueKind;
 
-/// Merges two iterators of different types into one. If the two iterators have the same key, only
-/// produce the key once and prefer the entry from A.
+/// Merges two iterators of different types into one. The versions of a key are produced from the
+/// newest to the oldest. If the two iterators have the same version, only produce it once and
+/// prefer the entry from A.
 pub struct TwoMergeIterator<A: StorageIterator, B: StorageIterator> {
     a: A,
     b: B,
@@ -18,12 +21,13 @@ impl<A: StorageIterator, B: StorageIterator> TwoMergeIterator<A, B> {
         if !b.is_valid() {
             return true;
         }
-        a.key() < b.key()
+        key::compare(a.key(), a.seq(), b.key(), b.seq()).is_lt()
     }
 
     fn skip_b(&mut self) -> Result<()> {
         if self.a.is_valid() {
-            while self.b.is_valid() && self.b.key() == self.a.key() {
+            while self.b.is_valid() && self.b.key() == self.a.key() && self.b.seq() == self.a.seq()
+            {
                 self.b.next()?;
             }
         }
@@ -59,6 +63,22 @@ impl<A: StorageIterator, B: StorageIterator> StorageIterator for TwoMergeIterato
         }
     }
 
+    fn kind(&self) -> ValueKind {
+        if self.choose_a {
+            self.a.kind()
+        } else {
+            self.b.kind()
+        }
+    }
+
+    fn seq(&self) -> u64 {
+        if self.choose_a {
+            self.a.seq()
+        } else {
+            self.b.seq()
+        }
+    }
+
     fn is_valid(&self) -> bool {
         if self.choose_a {
             self.a.is_valid()
@@ -0,0 +1,41 @@
+use std::cmp::Ordering;
+
+use bytes::Bytes;
+
+/// The largest sequence number. Seeking to it finds the newest version of a key.
+pub const MAX_SEQ: u64 = u64::MAX;
+
+/// A version of a key: the user key and the sequence number of the write that made it.
+///
+/// Every write gets a sequence number larger than all the writes before it, so that a reader can
+/// pick a sequence number and ignore everything written after it. Keys are ordered by the user
+/// key, and the versions of the same user key from the newest to the oldest, so that a reader
+/// meets the newest version it can see first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InternalKey {
+    pub key: Bytes,
+    pub seq: u64,
+}
+
+impl InternalKey {
+    pub fn new(key: Bytes, seq: u64) -> Self {
+        Self { key, seq }
+    }
+}
+
+impl Ord for InternalKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare(&self.key, self.seq, &other.key, other.seq)
+    }
+}
+
+impl PartialOrd for InternalKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Compare two versions of keys in the order described in [`InternalKey`].
+pub fn compare(key_a: &[u8], seq_a: u64, key_b: &[u8], seq_b: u64) -> Ordering {
+    key_a.cmp(key_b).then_with(|| seq_b.cmp(&seq_a))
+}
@@ -1,9 +1,21 @@
 pub mod block;
+pub mod checksum;
+pub mod compact;
 pub mod iterators;
+pub mod key;
 pub mod lsm_iterator;
 pub mod lsm_storage;
+pub mod manifest;
 pub mod mem_table;
+pub mod merge_operator;
+pub mod snapshot;
 pub mod table;
+pub mod transaction;
+pub mod value;
+pub mod varint;
+pub mod wal;
+pub mod write_batch;
+pub mod write_stall;
 
 #[cfg(test)]
 mod tests;
@@ -1,31 +1,58 @@
 use std::ops::Bound;
+use std::sync::Arc;
 
 use anyhow::Result;
 use bytes::Bytes;
 
+use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
 use crate::mem_table::MemTableIterator;
+use crate::merge_operator::{resolve_operands, MergeOperator};
 use crate::table::SsTableIterator;
+use crate::value::ValueKind;
 
-type LsmIteratorInner =
-    TwoMergeIterator<MergeIterator<MemTableIterator>, MergeIterator<SsTableIterator>>;
+/// Iterates over all versions of the keys in the memtables and the SSTs.
+pub(crate) type LsmIteratorInner = TwoMergeIterator<
+    TwoMergeIterator<MergeIterator<MemTableIterator>, MergeIterator<SsTableIterator>>,
+    MergeIterator<SstConcatIterator>,
+>;
 
+/// Iterates over the keys as of a sequence number. Only the newest version of each key that is
+/// not newer than the sequence number is produced, and keys whose version is a delete are skipped.
+/// If the newest versions are merge operands, they are combined with the older versions.
 pub struct LsmIterator {
     iter: LsmIteratorInner,
     end_bound: Bound<Bytes>,
+    /// Versions with a larger sequence number are ignored.
+    read_seq: u64,
     is_valid: bool,
+    /// The key whose versions are being skipped.
+    skipped_key: Vec<u8>,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// The sequence number and the value of `skipped_key` combined from its merge operands.
+    /// `iter` is already past the versions that were combined.
+    merged: Option<(u64, Bytes)>,
 }
 
 impl LsmIterator {
-    pub(crate) fn new(iter: LsmIteratorInner, end_bound: Bound<Bytes>) -> Result<Self> {
+    pub(crate) fn new(
+        iter: LsmIteratorInner,
+        end_bound: Bound<Bytes>,
+        read_seq: u64,
+        merge_operator: Option<Arc<dyn MergeOperator>>,
+    ) -> Result<Self> {
         let mut iter = Self {
             is_valid: iter.is_valid(),
             iter,
             end_bound,
+            read_seq,
+            skipped_key: Vec::new(),
+            merge_operator,
+            merged: None,
         };
-        iter.move_to_non_delete()?;
+        iter.move_to_visible()?;
         Ok(iter)
     }
 
@@ -43,30 +70,101 @@ impl LsmIterator {
         Ok(())
     }
 
-    fn move_to_non_delete(&mut self) -> Result<()> {
-        while self.is_valid() && self.iter.value().is_empty() {
+    /// Skip the older versions of the current key.
+    fn skip_current_key(&mut self) -> Result<()> {
+        if self.merged.take().is_none() {
+            self.skipped_key.clear();
+            self.skipped_key.extend_from_slice(self.iter.key());
+        }
+        while self.is_valid && self.iter.key() == self.skipped_key {
+            self.next_inner()?;
+        }
+        Ok(())
+    }
+
+    /// Move to the newest visible version of a key, unless it is a delete.
+    fn move_to_visible(&mut self) -> Result<()> {
+        loop {
+            while self.is_valid && self.iter.seq() > self.read_seq {
+                self.next_inner()?;
+            }
+            if !self.is_valid {
+                return Ok(());
+            }
+            match self.iter.kind() {
+                ValueKind::Put => return Ok(()),
+                ValueKind::Delete => self.skip_current_key()?,
+                ValueKind::Merge => return self.merge_current_key(),
+            }
+        }
+    }
+
+    /// Combine the merge operands of the current key with the version they apply to. The older
+    /// versions are all visible, since they are sorted by sequence number.
+    fn merge_current_key(&mut self) -> Result<()> {
+        self.skipped_key.clear();
+        self.skipped_key.extend_from_slice(self.iter.key());
+        let seq = self.iter.seq();
+        let mut operands = Vec::new();
+        let mut existing = None;
+        while self.is_valid && self.iter.key() == self.skipped_key {
+            match self.iter.kind() {
+                ValueKind::Merge => operands.push(Bytes::copy_from_slice(self.iter.value())),
+                ValueKind::Put => {
+                    existing = Some(Bytes::copy_from_slice(self.iter.value()));
+                    break;
+                }
+                ValueKind::Delete => break,
+            }
             self.next_inner()?;
         }
+        let value = resolve_operands(
+            self.merge_operator.as_ref(),
+            &self.skipped_key,
+            existing.as_deref(),
+            operands,
+        )?;
+        self.merged = Some((seq, value));
         Ok(())
     }
 }
 
 impl StorageIterator for LsmIterator {
     fn is_valid(&self) -> bool {
-        self.is_valid
+        self.merged.is_some() || self.is_valid
     }
 
     fn key(&self) -> &[u8] {
-        self.iter.key()
+        match self.merged {
+            Some(_) => &self.skipped_key,
+            None => self.iter.key(),
+        }
     }
 
     fn value(&self) -> &[u8] {
-        self.iter.value()
+        match &self.merged {
+            Some((_, value)) => value,
+            None => self.iter.value(),
+        }
+    }
+
+    fn kind(&self) -> ValueKind {
+        match self.merged {
+            Some(_) => ValueKind::Put,
+            None => self.iter.kind(),
+        }
+    }
+
+    fn seq(&self) -> u64 {
+        match self.merged {
+            Some((seq, _)) => seq,
+            None => self.iter.seq(),
+        }
     }
 
     fn next(&mut self) -> Result<()> {
-        self.next_inner()?;
-        self.move_to_non_delete()?;
+        self.skip_current_key()?;
+        self.move_to_visible()?;
         Ok(())
     }
 }
@@ -96,6 +194,14 @@ impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
         self.iter.value()
     }
 
+    fn kind(&self) -> ValueKind {
+        self.iter.kind()
+    }
+
+    fn seq(&self) -> u64 {
+        self.iter.seq()
+    }
+
     fn next(&mut self) -> Result<()> {
         // only move when the iterator is valid
         if self.iter.is_valid() {
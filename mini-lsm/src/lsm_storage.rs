@@ -1,181 +1,947 @@
+use std::collections::HashSet;
 use std::ops::Bound;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
 use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use bytes::Bytes;
-use parking_lot::{Mutex, RwLock};
+use parking_lot::{Condvar, Mutex, RwLock, RwLockWriteGuard};
 
 use crate::block::Block;
+use crate::compact::{CompactionController, CompactionFilter, CompactionOptions};
+use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
-use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::key::MAX_SEQ;
+use crate::lsm_iterator::{FusedIterator, LsmIterator, LsmIteratorInner};
+use crate::manifest::{Manifest, ManifestRecord};
 use crate::mem_table::{map_bound, MemTable};
-use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+use crate::merge_operator::MergeOperator;
+use crate::snapshot::{Snapshot, SnapshotList};
+use crate::table::{
+    FileObject, SsTable, SsTableBuilder, SsTableIterator, DEFAULT_BLOOM_BITS_PER_KEY,
+};
+use crate::transaction::{IsolationLevel, Transaction, WriteLog};
+use crate::value::ValueKind;
+use crate::wal::WalSyncPolicy;
+use crate::write_batch::WriteBatch;
+use crate::write_stall::{WriteStallCondition, WriteStallOptions};
 
 pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
 
+const MANIFEST_NAME: &str = "MANIFEST";
+
+/// How often the background threads check for work when they are not notified.
+pub(crate) const BACKGROUND_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Options for opening the storage.
+#[derive(Debug, Clone)]
+pub struct LsmStorageOptions {
+    /// Block size in bytes.
+    pub block_size: usize,
+    /// Size in bytes at which compaction starts a new SST.
+    pub target_sst_size: usize,
+    /// Size in bytes at which the current memtable is frozen and queued for flushing.
+    pub write_buffer_size: usize,
+    /// Number of bloom filter bits for each key in an SST. No filter is built if it is 0.
+    pub bloom_bits_per_key: usize,
+    /// When to `fsync` the write-ahead log.
+    pub wal_sync_policy: WalSyncPolicy,
+    /// How SSTs are compacted.
+    pub compaction_options: CompactionOptions,
+    /// Drops or rewrites entries while they are compacted.
+    pub compaction_filter: Option<Arc<dyn CompactionFilter>>,
+    /// Combines the operands written by `merge`. Merges fail if it is not set.
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// When writes are delayed or stopped to let the background threads catch up.
+    pub write_stall_options: WriteStallOptions,
+}
+
+impl Default for LsmStorageOptions {
+    fn default() -> Self {
+        Self {
+            block_size: 4096,
+            target_sst_size: 2 << 20,
+            write_buffer_size: 2 << 20,
+            bloom_bits_per_key: DEFAULT_BLOOM_BITS_PER_KEY,
+            wal_sync_policy: WalSyncPolicy::PerWrite,
+            compaction_options: CompactionOptions::default(),
+            compaction_filter: None,
+            merge_operator: None,
+            write_stall_options: WriteStallOptions::default(),
+        }
+    }
+}
+
+/// How useful the bloom filters of SSTs were in point lookups. Lookups in SSTs without a filter
+/// are not counted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BloomFilterStats {
+    /// Number of times a filter ruled out an SST, so that none of its blocks was read.
+    pub negative: u64,
+    /// Number of times a filter passed and the SST had the key, even if only in versions newer than
+    /// the lookup.
+    pub true_positive: u64,
+    /// Number of times a filter passed but the SST did not have the key.
+    pub false_positive: u64,
+}
+
+/// The error that writes fail with after a background flush or compaction failed.
+///
+/// The storage stops taking writes once that happens, since the data of a failed flush may not be
+/// durable anywhere but in the WALs. The data written before stays readable, and is recovered
+/// from the WALs when the storage is opened again.
+#[derive(Debug, Clone)]
+pub struct BackgroundError(pub Arc<anyhow::Error>);
+
+impl std::fmt::Display for BackgroundError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "background work failed: {:#}", self.0)
+    }
+}
+
+impl std::error::Error for BackgroundError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.0.as_ref().as_ref())
+    }
+}
+
+#[derive(Default)]
+struct BloomFilterCounters {
+    negative: AtomicU64,
+    true_positive: AtomicU64,
+    false_positive: AtomicU64,
+}
+
+impl BloomFilterCounters {
+    fn stats(&self) -> BloomFilterStats {
+        BloomFilterStats {
+            negative: self.negative.load(Ordering::Relaxed),
+            true_positive: self.true_positive.load(Ordering::Relaxed),
+            false_positive: self.false_positive.load(Ordering::Relaxed),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct LsmStorageInner {
     /// The current memtable.
-    memtable: Arc<MemTable>,
+    pub(crate) memtable: Arc<MemTable>,
     /// Immutable memTables, from earliest to latest.
-    imm_memtables: Vec<Arc<MemTable>>,
+    pub(crate) imm_memtables: Vec<Arc<MemTable>>,
     /// L0 SsTables, from earliest to latest.
-    l0_sstables: Vec<Arc<SsTable>>,
-    /// L1 - L6 SsTables, sorted by key range.
-    #[allow(dead_code)]
-    levels: Vec<Vec<Arc<SsTable>>>,
-    /// The next SSTable ID.
-    next_sst_id: usize,
+    pub(crate) l0_sstables: Vec<Arc<SsTable>>,
+    /// The levels below L0, from the newest data to the oldest. The SSTs of a level do not overlap
+    /// and are sorted by key range. In tiered compaction, each non-empty level is a tier.
+    pub(crate) levels: Vec<Vec<Arc<SsTable>>>,
 }
 
 impl LsmStorageInner {
-    fn create() -> Self {
-        Self {
-            memtable: Arc::new(MemTable::create()),
-            imm_memtables: vec![],
-            l0_sstables: vec![],
-            levels: vec![],
-            next_sst_id: 1,
+    /// Records that rebuild the current set of SSTs when replayed into an empty manifest.
+    pub(crate) fn manifest_records(&self, next_sst_id: usize) -> Vec<ManifestRecord> {
+        let mut records = vec![ManifestRecord::NextSstId(next_sst_id)];
+        for table in &self.l0_sstables {
+            records.push(ManifestRecord::AddSst {
+                level: 0,
+                id: table.id(),
+            });
         }
+        for (idx, level) in self.levels.iter().enumerate() {
+            for table in level {
+                records.push(ManifestRecord::AddSst {
+                    level: idx + 1,
+                    id: table.id(),
+                });
+            }
+        }
+        records
     }
 }
 
-/// The storage interface of the LSM tree.
-pub struct LsmStorage {
-    inner: Arc<RwLock<Arc<LsmStorageInner>>>,
+/// The storage engine, shared by [`LsmStorage`] and its background threads.
+pub(crate) struct LsmStorageCore {
+    pub(crate) inner: Arc<RwLock<Arc<LsmStorageInner>>>,
     flush_lock: Mutex<()>,
+    /// Held while the set of SSTs is changed, so that the manifest and `inner` are updated in the
+    /// same order.
+    pub(crate) state_lock: Mutex<()>,
+    /// Held while compacting, so that there is at most one compaction at a time.
+    pub(crate) compaction_lock: Mutex<()>,
     path: PathBuf,
-    block_cache: Arc<BlockCache>,
+    pub(crate) block_cache: Arc<BlockCache>,
+    pub(crate) manifest: Manifest,
+    pub(crate) next_sst_id: AtomicUsize,
+    pub(crate) compaction_controller: CompactionController,
+    pub(crate) options: LsmStorageOptions,
+    /// Wakes up the flush thread. The thread exits once this is dropped.
+    flush_notifier: Mutex<Option<Sender<()>>>,
+    /// Signaled after a memtable is flushed or a compaction finishes, to wake up stalled writes.
+    pub(crate) background_work_done: Condvar,
+    pub(crate) background_work_done_lock: Mutex<()>,
+    /// Wakes up the compaction thread. The thread exits once this is dropped.
+    compaction_notifier: Mutex<Option<Sender<()>>>,
+    /// The first error of a background flush or compaction.
+    background_error: Mutex<Option<Arc<anyhow::Error>>>,
+    bloom_filter_counters: BloomFilterCounters,
+    /// Held while a write is given a sequence number and applied, so that writes become visible in
+    /// the order of their sequence numbers.
+    write_lock: Mutex<()>,
+    /// The sequence number of the last write. All writes up to it are visible to readers.
+    last_seq: AtomicU64,
+    pub(crate) snapshots: SnapshotList,
+    pub(crate) write_log: WriteLog,
+}
+
+/// The storage interface of the LSM tree.
+///
+/// Frozen memtables are flushed and SSTs are compacted by background threads. They are stopped by
+/// [`LsmStorage::close`], or when the storage is dropped.
+pub struct LsmStorage {
+    core: Arc<LsmStorageCore>,
+    flush_thread: Mutex<Option<JoinHandle<()>>>,
+    compaction_thread: Mutex<Option<JoinHandle<()>>>,
 }
 
 impl LsmStorage {
     pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_options(path, LsmStorageOptions::default())
+    }
+
+    /// Open the storage at `path`. The SSTs are restored from the manifest, and the WALs of
+    /// memtables that were not flushed before the last shutdown are replayed into immutable
+    /// memtables.
+    pub fn open_with_options(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Self> {
+        let (flush_tx, flush_rx) = mpsc::channel();
+        let (compaction_tx, compaction_rx) = mpsc::channel();
+        let core = Arc::new(LsmStorageCore::open(
+            path,
+            options,
+            flush_tx,
+            compaction_tx,
+        )?);
+        let flush_thread = core.spawn_flush_thread(flush_rx)?;
+        let compaction_thread = core.spawn_compaction_thread(compaction_rx)?;
         Ok(Self {
-            inner: Arc::new(RwLock::new(Arc::new(LsmStorageInner::create()))),
+            core,
+            flush_thread: Mutex::new(Some(flush_thread)),
+            compaction_thread: Mutex::new(Some(compaction_thread)),
+        })
+    }
+
+    /// Get a key from the storage.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.core.get(key)
+    }
+
+    /// Get a key as of the sequence number `seq`, ignoring the writes after it.
+    ///
+    /// Compaction only keeps the versions that the latest state and the live snapshots can see, so
+    /// the versions an older sequence number would see may be gone. Use [`LsmStorage::snapshot`]
+    /// for reads that must stay consistent.
+    pub fn get_at_seq(&self, key: &[u8], seq: u64) -> Result<Option<Bytes>> {
+        self.core.get_at_seq(key, seq)
+    }
+
+    /// Get the sequence number of the last write. Reading at it sees all writes so far.
+    pub fn latest_seq(&self) -> u64 {
+        self.core.latest_seq()
+    }
+
+    /// Take a snapshot of the storage. Its reads see the storage as it is now, until it is dropped.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(self.core.clone())
+    }
+
+    /// Begin an optimistic transaction, which reads the storage as it is now.
+    pub fn begin_transaction(&self) -> Transaction {
+        self.begin_transaction_with_isolation(IsolationLevel::default())
+    }
+
+    /// Begin an optimistic transaction at an isolation level.
+    pub fn begin_transaction_with_isolation(&self, isolation: IsolationLevel) -> Transaction {
+        Transaction::new(self.core.clone(), isolation)
+    }
+
+    /// Put a key-value pair into the storage.
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.core.put(key, value)
+    }
+
+    /// Remove a key from the storage.
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        self.core.delete(key)
+    }
+
+    /// Write a merge operand of a key, which the merge operator applies to the value of the key.
+    pub fn merge(&self, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.core.merge(key, operand)
+    }
+
+    /// Apply all writes of a batch atomically, so that readers see either all of them or none.
+    pub fn write(&self, batch: &WriteBatch) -> Result<()> {
+        self.core.write(batch)
+    }
+
+    /// Flush all memtables to SSTs.
+    pub fn sync(&self) -> Result<()> {
+        self.core.sync()
+    }
+
+    /// Get whether writes are currently stalled, and why.
+    pub fn write_stall_condition(&self) -> WriteStallCondition {
+        self.core.write_stall_condition()
+    }
+
+    /// Run compactions until no level is over its limit, without waiting for the compaction
+    /// thread.
+    pub fn compact(&self) -> Result<()> {
+        self.core.compact()
+    }
+
+    /// Merge all SSTs that overlap a key range into the last level, so that the space taken by
+    /// overwritten and deleted keys in the range is reclaimed. Memtables with keys in the range
+    /// are flushed first. Blocks until the compaction is done.
+    ///
+    /// Fails with FIFO compaction, which never merges SSTs.
+    pub fn compact_range(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<()> {
+        self.core.compact_range(lower, upper)
+    }
+
+    /// Get how often the bloom filters helped point lookups since the storage was opened.
+    pub fn bloom_filter_stats(&self) -> BloomFilterStats {
+        self.core.bloom_filter_counters.stats()
+    }
+
+    /// Create an iterator over a range of keys.
+    pub fn scan(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.core.scan(lower, upper)
+    }
+
+    /// Create an iterator over a range of keys as of the sequence number `seq`, ignoring the
+    /// writes after it.
+    pub fn scan_at_seq(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        seq: u64,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.core.scan_at_seq(lower, upper, seq)
+    }
+
+    /// Stop the background threads, waiting for the jobs they are running, and flush the current
+    /// memtable. Calling it more than once does nothing. Fails with a [`BackgroundError`] if a
+    /// background flush or compaction failed.
+    pub fn close(&self) -> Result<()> {
+        if self.stop_background_threads()? {
+            self.core.sync()?;
+        }
+        self.core.check_background_error()
+    }
+
+    /// Stop the background threads, waiting for the jobs they are running. Returns false if they
+    /// were already stopped.
+    pub(crate) fn stop_background_threads(&self) -> Result<bool> {
+        let flush_thread = self.flush_thread.lock().take();
+        let compaction_thread = self.compaction_thread.lock().take();
+        if flush_thread.is_none() && compaction_thread.is_none() {
+            return Ok(false);
+        }
+        self.core.flush_notifier.lock().take();
+        self.core.compaction_notifier.lock().take();
+        for thread in flush_thread.into_iter().chain(compaction_thread) {
+            thread
+                .join()
+                .map_err(|e| anyhow!("background thread panicked: {:?}", e))?;
+        }
+        Ok(true)
+    }
+
+    /// Stop the background threads and drop the storage without flushing the current memtable, as
+    /// if the process crashed.
+    #[cfg(test)]
+    pub(crate) fn crash(self) {
+        // Once the threads are stopped, the `close` in `drop` does not flush.
+        self.stop_background_threads().unwrap();
+    }
+
+    #[cfg(test)]
+    pub(crate) fn core(&self) -> &LsmStorageCore {
+        &self.core
+    }
+}
+
+impl Drop for LsmStorage {
+    fn drop(&mut self) {
+        // There is no way to report an error from here. Call `close` to see it.
+        let _ = self.close();
+    }
+}
+
+impl LsmStorageCore {
+    fn open(
+        path: impl AsRef<Path>,
+        options: LsmStorageOptions,
+        flush_notifier: Sender<()>,
+        compaction_notifier: Sender<()>,
+    ) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        std::fs::create_dir_all(&path)?;
+        let block_cache = Arc::new(BlockCache::new(1 << 20)); // 4GB block cache
+
+        // Replay the manifest to find out which SSTs are live and in which level.
+        let manifest_path = path.join(MANIFEST_NAME);
+        let (manifest, records) = if manifest_path.exists() {
+            Manifest::recover(&manifest_path)?
+        } else {
+            (Manifest::create(&manifest_path)?, Vec::new())
+        };
+        let mut l0_ids = Vec::new();
+        let mut level_ids: Vec<Vec<usize>> = Vec::new();
+        let mut next_sst_id = 1;
+        for record in records {
+            match record {
+                ManifestRecord::AddSst { level: 0, id } => l0_ids.push(id),
+                ManifestRecord::AddSst { level, id } => {
+                    if level_ids.len() < level {
+                        level_ids.resize_with(level, Vec::new);
+                    }
+                    level_ids[level - 1].push(id);
+                }
+                ManifestRecord::RemoveSst { id } => {
+                    l0_ids.retain(|x| *x != id);
+                    for level in &mut level_ids {
+                        level.retain(|x| *x != id);
+                    }
+                }
+                ManifestRecord::NextSstId(id) => next_sst_id = next_sst_id.max(id),
+            }
+        }
+        let live_ids: HashSet<usize> = l0_ids
+            .iter()
+            .chain(level_ids.iter().flatten())
+            .copied()
+            .collect();
+        for id in &live_ids {
+            next_sst_id = next_sst_id.max(id + 1);
+        }
+
+        let open_sst = |id: usize| -> Result<Arc<SsTable>> {
+            let file = FileObject::open(&Self::path_of_sst_static(&path, id))?;
+            Ok(Arc::new(SsTable::open(
+                id,
+                Some(block_cache.clone()),
+                file,
+            )?))
+        };
+        let l0_sstables = l0_ids
+            .into_iter()
+            .map(open_sst)
+            .collect::<Result<Vec<_>>>()?;
+        let compaction_controller = CompactionController::new(options.compaction_options.clone());
+        level_ids.resize_with(
+            level_ids.len().max(compaction_controller.num_levels()),
+            Vec::new,
+        );
+        let mut levels = Vec::with_capacity(level_ids.len());
+        for ids in level_ids {
+            let mut level = ids.into_iter().map(open_sst).collect::<Result<Vec<_>>>()?;
+            level.sort_by(|a, b| a.first_key().cmp(b.first_key()));
+            levels.push(level);
+        }
+
+        // Clean up SSTs that never made it into the manifest, and WALs whose memtable was
+        // already flushed, then collect the WALs to replay.
+        let mut wal_ids = Vec::new();
+        for entry in std::fs::read_dir(&path)? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let file_name = file_name.to_string_lossy();
+            let (id, ext) = match file_name.split_once('.') {
+                Some((id, ext)) => (id.parse::<usize>(), ext),
+                None => continue,
+            };
+            let id = match id {
+                Ok(id) => id,
+                Err(_) => continue,
+            };
+            match ext {
+                "wal" if live_ids.contains(&id) => std::fs::remove_file(entry.path())?,
+                "wal" => {
+                    wal_ids.push(id);
+                    next_sst_id = next_sst_id.max(id + 1);
+                }
+                "sst" if !live_ids.contains(&id) => std::fs::remove_file(entry.path())?,
+                _ => {}
+            }
+        }
+        wal_ids.sort_unstable();
+
+        let mut imm_memtables = Vec::with_capacity(wal_ids.len());
+        for id in wal_ids {
+            let wal_path = Self::path_of_wal_static(&path, id);
+            let memtable = MemTable::recover_from_wal(id, &wal_path, options.wal_sync_policy)?;
+            if memtable.is_empty() {
+                std::fs::remove_file(&wal_path)?;
+                continue;
+            }
+            imm_memtables.push(Arc::new(memtable));
+        }
+        let memtable_id = next_sst_id;
+        let memtable = MemTable::create_with_wal(
+            memtable_id,
+            Self::path_of_wal_static(&path, memtable_id),
+            options.wal_sync_policy,
+        )?;
+        Self::sync_dir(&path)?;
+
+        let inner = LsmStorageInner {
+            memtable: Arc::new(memtable),
+            imm_memtables,
+            l0_sstables,
+            levels,
+        };
+        if manifest.needs_rewrite() {
+            manifest.rewrite(&inner.manifest_records(memtable_id + 1))?;
+        }
+        // New writes continue after the largest sequence number that was persisted.
+        let last_seq = inner
+            .l0_sstables
+            .iter()
+            .chain(inner.levels.iter().flatten())
+            .map(|table| table.properties().max_seq)
+            .chain(
+                inner
+                    .imm_memtables
+                    .iter()
+                    .map(|memtable| memtable.max_seq()),
+            )
+            .max()
+            .unwrap_or(0);
+
+        Ok(Self {
+            inner: Arc::new(RwLock::new(Arc::new(inner))),
             flush_lock: Mutex::new(()),
-            path: path.as_ref().to_path_buf(),
-            block_cache: Arc::new(BlockCache::new(1 << 20)), // 4GB block cache
+            state_lock: Mutex::new(()),
+            compaction_lock: Mutex::new(()),
+            path,
+            block_cache,
+            manifest,
+            next_sst_id: AtomicUsize::new(memtable_id + 1),
+            compaction_controller,
+            options,
+            flush_notifier: Mutex::new(Some(flush_notifier)),
+            background_work_done: Condvar::new(),
+            background_work_done_lock: Mutex::new(()),
+            compaction_notifier: Mutex::new(Some(compaction_notifier)),
+            background_error: Mutex::new(None),
+            bloom_filter_counters: BloomFilterCounters::default(),
+            write_lock: Mutex::new(()),
+            last_seq: AtomicU64::new(last_seq),
+            snapshots: SnapshotList::default(),
+            write_log: WriteLog::default(),
         })
     }
 
-    /// Get a key from the storage. In day 7, this can be further optimized by using a bloom filter.
+    /// Get the sequence number of the last write.
+    pub fn latest_seq(&self) -> u64 {
+        self.last_seq.load(Ordering::Acquire)
+    }
+
+    /// Get a key from the storage.
     pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.get_at_seq(key, self.latest_seq())
+    }
+
+    /// Get a key as of the sequence number `seq`.
+    pub fn get_at_seq(&self, key: &[u8], seq: u64) -> Result<Option<Bytes>> {
         let snapshot = {
             let guard = self.inner.read();
             Arc::clone(&guard)
         }; // drop global lock here
+        self.get_from_state(&snapshot, key, seq)
+    }
 
-        // Search on the current memtable.
-        if let Some(value) = snapshot.memtable.get(key) {
-            if value.is_empty() {
-                // found tomestone, return key not exists
-                return Ok(None);
+    /// Get a key as of the sequence number `seq` from a state of the storage.
+    pub(crate) fn get_from_state(
+        &self,
+        snapshot: &LsmStorageInner,
+        key: &[u8],
+        seq: u64,
+    ) -> Result<Option<Bytes>> {
+        // Search from the newest data to the oldest, and stop at the first version of the key
+        // that is visible at `seq`.
+        let entry = match self.get_from_memtables(snapshot, key, seq) {
+            Some(entry) => Some(entry),
+            None => self.get_from_ssts(snapshot, key, seq)?,
+        };
+        match entry {
+            Some((ValueKind::Put, value)) => Ok(Some(value)),
+            Some((ValueKind::Delete, _)) | None => Ok(None),
+            // The operands have to be combined with the older versions, so read all of them.
+            Some((ValueKind::Merge, _)) => {
+                let key = Bound::Included(key);
+                let iter = self.scan_from_state(snapshot, key, key, seq)?;
+                Ok(iter
+                    .is_valid()
+                    .then(|| Bytes::copy_from_slice(iter.value())))
             }
-            return Ok(Some(value));
         }
-        // Search on immutable memtables.
-        for memtable in snapshot.imm_memtables.iter().rev() {
-            if let Some(value) = memtable.get(key) {
-                if value.is_empty() {
-                    // found tomestone, return key not exists
-                    return Ok(None);
-                }
-                return Ok(Some(value));
-            }
+    }
+
+    fn get_from_memtables(
+        &self,
+        snapshot: &LsmStorageInner,
+        key: &[u8],
+        seq: u64,
+    ) -> Option<(ValueKind, Bytes)> {
+        if let Some(entry) = snapshot.memtable.get(key, seq) {
+            return Some(entry);
         }
-        let mut iters = Vec::new();
-        iters.reserve(snapshot.l0_sstables.len());
+        // Immutable memtables are searched from the latest to the earliest.
+        snapshot
+            .imm_memtables
+            .iter()
+            .rev()
+            .find_map(|memtable| memtable.get(key, seq))
+    }
+
+    fn get_from_ssts(
+        &self,
+        snapshot: &LsmStorageInner,
+        key: &[u8],
+        seq: u64,
+    ) -> Result<Option<(ValueKind, Bytes)>> {
+        // L0 SSTs may overlap, so all of them are searched from the latest to the earliest.
         for table in snapshot.l0_sstables.iter().rev() {
-            iters.push(Box::new(SsTableIterator::create_and_seek_to_key(
-                table.clone(),
-                key,
-            )?));
+            if let Some(entry) = self.get_from_sst(table, key, seq)? {
+                return Ok(Some(entry));
+            }
         }
-        let iter = MergeIterator::create(iters);
-        if iter.is_valid() {
-            return Ok(Some(Bytes::copy_from_slice(iter.value())));
+        // SSTs in a level do not overlap, so at most one SST per level may contain the key.
+        for level in &snapshot.levels {
+            let idx = level.partition_point(|table| table.first_key() <= key);
+            if idx == 0 {
+                continue;
+            }
+            if let Some(entry) = self.get_from_sst(&level[idx - 1], key, seq)? {
+                return Ok(Some(entry));
+            }
         }
         Ok(None)
     }
 
+    /// Look up `key` as of `seq` in an SST, unless its key range or bloom filter rules the key
+    /// out.
+    fn get_from_sst(
+        &self,
+        table: &SsTable,
+        key: &[u8],
+        seq: u64,
+    ) -> Result<Option<(ValueKind, Bytes)>> {
+        if key < &table.first_key()[..] || key > &table.last_key()[..] {
+            return Ok(None);
+        }
+        // The stats are about filters, so SSTs without one are not counted.
+        if !table.has_bloom_filter() {
+            return table.get(key, seq);
+        }
+        let counters = &self.bloom_filter_counters;
+        if !table.may_contain(key) {
+            counters.negative.fetch_add(1, Ordering::Relaxed);
+            return Ok(None);
+        }
+        let entry = table.get(key, seq)?;
+        // The filter is right if the SST has the key, even if only in newer versions.
+        if entry.is_some() || table.get(key, MAX_SEQ)?.is_some() {
+            counters.true_positive.fetch_add(1, Ordering::Relaxed);
+        } else {
+            counters.false_positive.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(entry)
+    }
+
     /// Put a key-value pair into the storage by writing into the current memtable.
     pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
-        assert!(!value.is_empty(), "value cannot be empty");
-        assert!(!key.is_empty(), "key cannot be empty");
+        self.write(WriteBatch::new().put(key, value))
+    }
 
-        let guard = self.inner.read();
-        guard.memtable.put(key, value);
+    /// Remove a key from the storage by writing a delete into the current memtable.
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        self.write(WriteBatch::new().delete(key))
+    }
 
-        Ok(())
+    /// Write a merge operand into the current memtable.
+    pub fn merge(&self, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.write(WriteBatch::new().merge(key, operand))
     }
 
-    /// Remove a key from the storage by writing an empty value.
-    pub fn delete(&self, key: &[u8]) -> Result<()> {
-        assert!(!key.is_empty(), "key cannot be empty");
+    /// Write a batch into the current memtable with the next sequence number, and freeze the
+    /// memtable if it grows too large.
+    pub fn write(&self, batch: &WriteBatch) -> Result<()> {
+        self.write_with_check(batch, || Ok(()))
+    }
 
-        let guard = self.inner.read();
-        guard.memtable.put(key, b"");
+    /// Write a batch like `write`, if `check` succeeds. No other write can happen between the
+    /// check and the write.
+    pub(crate) fn write_with_check(
+        &self,
+        batch: &WriteBatch,
+        check: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.check_background_error()?;
+        let batch = batch.resolve_operands(self.options.merge_operator.as_ref())?;
+        let batch = batch.as_ref();
+        if self.options.merge_operator.is_none()
+            && batch
+                .entries()
+                .iter()
+                .any(|entry| entry.kind == ValueKind::Merge)
+        {
+            bail!("cannot merge without a merge operator");
+        }
+        self.wait_for_write_stall()?;
+        let size = {
+            let _write_lock = self.write_lock.lock();
+            check()?;
+            let seq = self.last_seq.load(Ordering::Relaxed) + 1;
+            let guard = self.inner.read();
+            guard.memtable.write_batch(batch, seq)?;
+            // Readers only see the batch once the sequence number is published.
+            self.last_seq.store(seq, Ordering::Release);
+            self.write_log.add(seq, batch);
+            guard.memtable.approximate_size()
+        };
+        self.try_freeze(size)
+    }
 
+    /// Freeze the current memtable if its size, as seen by the caller, reached
+    /// `write_buffer_size`.
+    fn try_freeze(&self, estimated_size: usize) -> Result<()> {
+        if estimated_size < self.options.write_buffer_size {
+            return Ok(());
+        }
+        let mut guard = self.inner.write();
+        // Another write may have frozen the memtable in the meantime.
+        if guard.memtable.approximate_size() >= self.options.write_buffer_size {
+            self.freeze_memtable(&mut guard)?;
+            drop(guard);
+            self.notify_flush();
+        }
         Ok(())
     }
 
-    fn path_of_sst(&self, id: usize) -> PathBuf {
-        self.path.join(format!("{:05}.sst", id))
+    /// Create a builder for a new SST.
+    pub(crate) fn new_sst_builder(&self) -> SsTableBuilder {
+        SsTableBuilder::new(self.options.block_size)
+            .with_bloom_bits_per_key(self.options.bloom_bits_per_key)
     }
 
-    /// Persist data to disk.
-    ///
-    /// In day 3: flush the current memtable to disk as L0 SST.
-    /// In day 6: call `fsync` on WAL.
+    fn path_of_sst_static(path: &Path, id: usize) -> PathBuf {
+        path.join(format!("{:05}.sst", id))
+    }
+
+    pub(crate) fn path_of_sst(&self, id: usize) -> PathBuf {
+        Self::path_of_sst_static(&self.path, id)
+    }
+
+    fn path_of_wal_static(path: &Path, id: usize) -> PathBuf {
+        path.join(format!("{:05}.wal", id))
+    }
+
+    fn path_of_wal(&self, id: usize) -> PathBuf {
+        Self::path_of_wal_static(&self.path, id)
+    }
+
+    pub(crate) fn sync_dir(path: &Path) -> Result<()> {
+        std::fs::File::open(path)?.sync_all()?;
+        Ok(())
+    }
+
+    /// Freeze the current memtable and flush all immutable memtables to L0 SSTs, so that everything
+    /// written so far is in SSTs.
     pub fn sync(&self) -> Result<()> {
+        self.check_background_error()?;
+        // Make the writes durable in the WAL first, so that they are not lost if the flush fails,
+        // whatever the `WalSyncPolicy`.
+        let memtable = self.inner.read().memtable.clone();
+        memtable.sync_wal()?;
+        self.force_freeze_memtable()?;
+        self.flush_imm_memtables()
+    }
+
+    /// Move the current memtable to the immutable memtables, unless it is empty.
+    fn force_freeze_memtable(&self) -> Result<()> {
+        let mut guard = self.inner.write();
+        if guard.memtable.is_empty() {
+            return Ok(());
+        }
+        self.freeze_memtable(&mut guard)
+    }
+
+    fn freeze_memtable(&self, guard: &mut RwLockWriteGuard<Arc<LsmStorageInner>>) -> Result<()> {
+        // Swap the current memtable with a new one.
+        let mut snapshot = guard.as_ref().clone();
+        let memtable_id = self.next_sst_id.fetch_add(1, Ordering::SeqCst);
+        let memtable = MemTable::create_with_wal(
+            memtable_id,
+            self.path_of_wal(memtable_id),
+            self.options.wal_sync_policy,
+        )?;
+        let memtable = std::mem::replace(&mut snapshot.memtable, Arc::new(memtable));
+        // Add the memtable to the immutable memtables.
+        snapshot.imm_memtables.push(memtable);
+        // Update the snapshot.
+        **guard = Arc::new(snapshot);
+        Ok(())
+    }
+
+    /// Flush all immutable memtables to L0 SSTs, from the earliest to the latest.
+    pub(crate) fn flush_imm_memtables(&self) -> Result<()> {
         let _flush_lock = self.flush_lock.lock();
 
-        let flush_memtable;
-        let sst_id;
+        // Immutable memtables are disabled for write, and all write threads are operating on the
+        // current memtable. We can safely flush them to disk.
+        let mut flushed = false;
+        loop {
+            let flush_memtable = {
+                let guard = self.inner.read();
+                match guard.imm_memtables.first() {
+                    Some(memtable) => memtable.clone(),
+                    None => break,
+                }
+            };
+            let sst_id = flush_memtable.id();
 
-        // Move mutable memtable to immutable memtables.
-        {
-            let mut guard = self.inner.write();
-            // Swap the current memtable with a new one.
-            let mut snapshot = guard.as_ref().clone();
-            let memtable = std::mem::replace(&mut snapshot.memtable, Arc::new(MemTable::create()));
-            flush_memtable = memtable.clone();
-            sst_id = snapshot.next_sst_id;
-            // Add the memtable to the immutable memtables.
-            snapshot.imm_memtables.push(memtable);
-            // Update the snapshot.
-            *guard = Arc::new(snapshot);
-        }
-
-        // At this point, the old memtable should be disabled for write, and all write threads
-        // should be operating on the new memtable. We can safely flush the old memtable to
-        // disk.
-
-        let mut builder = SsTableBuilder::new(4096);
-        flush_memtable.flush(&mut builder)?;
-        let sst = Arc::new(builder.build(
-            sst_id,
-            Some(self.block_cache.clone()),
-            self.path_of_sst(sst_id),
-        )?);
+            // An empty memtable does not produce an SST.
+            let sst = if flush_memtable.is_empty() {
+                None
+            } else {
+                let mut builder = self.new_sst_builder();
+                flush_memtable.flush(&mut builder)?;
+                Some(Arc::new(builder.build(
+                    sst_id,
+                    Some(self.block_cache.clone()),
+                    self.path_of_sst(sst_id),
+                )?))
+            };
 
-        // Add the flushed L0 table to the list.
-        {
-            let mut guard = self.inner.write();
-            let mut snapshot = guard.as_ref().clone();
-            // Remove the memtable from the immutable memtables.
-            snapshot.imm_memtables.pop();
-            // Add L0 table
-            snapshot.l0_sstables.push(sst);
-            // Update SST ID
-            snapshot.next_sst_id += 1;
-            // Update the snapshot.
-            *guard = Arc::new(snapshot);
+            // Record the new L0 table in the manifest before it becomes visible, so that it is
+            // never lost once the WAL is removed.
+            let _state_lock = self.state_lock.lock();
+            if sst.is_some() {
+                let next_sst_id = self.next_sst_id.load(Ordering::SeqCst);
+                self.manifest.add_records(&[
+                    ManifestRecord::AddSst {
+                        level: 0,
+                        id: sst_id,
+                    },
+                    ManifestRecord::NextSstId(next_sst_id),
+                ])?;
+            }
+
+            // Add the flushed L0 table to the list.
+            {
+                let mut guard = self.inner.write();
+                let mut snapshot = guard.as_ref().clone();
+                // Remove the memtable from the immutable memtables.
+                let memtable = snapshot.imm_memtables.remove(0);
+                assert_eq!(memtable.id(), sst_id);
+                // Add L0 table
+                snapshot.l0_sstables.extend(sst);
+                // Update the snapshot.
+                *guard = Arc::new(snapshot);
+            }
+
+            // The data is now durable in the SST, so the WAL is no longer needed.
+            std::fs::remove_file(self.path_of_wal(sst_id))?;
+            self.maybe_rewrite_manifest()?;
+            flushed = true;
+
+            self.notify_background_work_done();
+        }
+        if flushed {
+            Self::sync_dir(&self.path)?;
+            self.notify_compaction();
+        }
+        Ok(())
+    }
+
+    /// Wake up the flush thread. Returns false if it is stopped.
+    pub(crate) fn notify_flush(&self) -> bool {
+        match self.flush_notifier.lock().as_ref() {
+            Some(notifier) => notifier.send(()).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Wake up the compaction thread. Returns false if it is stopped.
+    pub(crate) fn notify_compaction(&self) -> bool {
+        match self.compaction_notifier.lock().as_ref() {
+            Some(notifier) => notifier.send(()).is_ok(),
+            None => false,
+        }
+    }
+
+    pub(crate) fn notify_background_work_done(&self) {
+        let _guard = self.background_work_done_lock.lock();
+        self.background_work_done.notify_all();
+    }
+
+    /// Remember the error of a background flush or compaction, unless an earlier one is already
+    /// remembered, and wake up the stalled writes to fail them.
+    pub(crate) fn set_background_error(&self, error: anyhow::Error) {
+        self.background_error
+            .lock()
+            .get_or_insert_with(|| Arc::new(error));
+        self.notify_background_work_done();
+    }
+
+    /// Fail with a [`BackgroundError`] if a background flush or compaction failed.
+    pub(crate) fn check_background_error(&self) -> Result<()> {
+        match self.background_error.lock().as_ref() {
+            Some(error) => Err(BackgroundError(error.clone()).into()),
+            None => Ok(()),
         }
+    }
+
+    fn spawn_flush_thread(self: &Arc<Self>, rx: Receiver<()>) -> Result<JoinHandle<()>> {
+        let this = self.clone();
+        let handle = std::thread::Builder::new()
+            .name("mini-lsm-flush".to_string())
+            .spawn(move || loop {
+                match rx.recv_timeout(BACKGROUND_CHECK_INTERVAL) {
+                    Ok(()) | Err(RecvTimeoutError::Timeout) => {
+                        if let Err(e) = this.flush_imm_memtables() {
+                            this.set_background_error(e);
+                        }
+                    }
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            })?;
+        Ok(handle)
+    }
 
+    /// Rewrite the manifest if it grew too large. Must be called with `state_lock` held.
+    pub(crate) fn maybe_rewrite_manifest(&self) -> Result<()> {
+        if self.manifest.needs_rewrite() {
+            let records = self
+                .inner
+                .read()
+                .manifest_records(self.next_sst_id.load(Ordering::SeqCst));
+            self.manifest.rewrite(&records)?;
+        }
         Ok(())
     }
 
@@ -184,12 +950,50 @@ impl LsmStorage {
         &self,
         lower: Bound<&[u8]>,
         upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.scan_at_seq(lower, upper, self.latest_seq())
+    }
+
+    /// Create an iterator over a range of keys as of the sequence number `seq`.
+    pub fn scan_at_seq(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        seq: u64,
     ) -> Result<FusedIterator<LsmIterator>> {
         let snapshot = {
             let guard = self.inner.read();
             Arc::clone(&guard)
         }; // drop global lock here
+        self.scan_from_state(&snapshot, lower, upper, seq)
+    }
 
+    /// Create an iterator over a range of keys as of the sequence number `seq` from a state of the
+    /// storage.
+    pub(crate) fn scan_from_state(
+        &self,
+        snapshot: &LsmStorageInner,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        seq: u64,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        let iter = self.scan_versions_from_state(snapshot, lower, upper)?;
+        Ok(FusedIterator::new(LsmIterator::new(
+            iter,
+            map_bound(upper),
+            seq,
+            self.options.merge_operator.clone(),
+        )?))
+    }
+
+    /// Create an iterator over all versions of a range of keys, including deletes, from a state of
+    /// the storage.
+    fn scan_versions_from_state(
+        &self,
+        snapshot: &LsmStorageInner,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<LsmIteratorInner> {
         let mut memtable_iters = Vec::new();
         memtable_iters.reserve(snapshot.imm_memtables.len() + 1);
         memtable_iters.push(Box::new(snapshot.memtable.scan(lower, upper)));
@@ -201,29 +1005,66 @@ impl LsmStorage {
         let mut table_iters = Vec::new();
         table_iters.reserve(snapshot.l0_sstables.len());
         for table in snapshot.l0_sstables.iter().rev() {
-            let iter = match lower {
-                Bound::Included(key) => {
+            if !range_overlap(lower, upper, table) {
+                continue;
+            }
+            let mut iter = match lower {
+                Bound::Included(key) | Bound::Excluded(key) => {
                     SsTableIterator::create_and_seek_to_key(table.clone(), key)?
                 }
-                Bound::Excluded(key) => {
-                    let mut iter = SsTableIterator::create_and_seek_to_key(table.clone(), key)?;
-                    if iter.is_valid() && iter.key() == key {
-                        iter.next()?;
-                    }
-                    iter
-                }
                 Bound::Unbounded => SsTableIterator::create_and_seek_to_first(table.clone())?,
             };
-
+            iter.set_upper_bound(upper);
+            if let Bound::Excluded(key) = lower {
+                while iter.is_valid() && iter.key() == key {
+                    iter.next()?;
+                }
+            }
             table_iters.push(Box::new(iter));
         }
         let table_iter = MergeIterator::create(table_iters);
 
-        let iter = TwoMergeIterator::create(memtable_iter, table_iter)?;
+        let mut level_iters = Vec::with_capacity(snapshot.levels.len());
+        for level in &snapshot.levels {
+            let level: Vec<_> = level
+                .iter()
+                .filter(|table| range_overlap(lower, upper, table))
+                .cloned()
+                .collect();
+            let mut iter = match lower {
+                Bound::Included(key) | Bound::Excluded(key) => {
+                    SstConcatIterator::create_and_seek_to_key(level, key)?
+                }
+                Bound::Unbounded => SstConcatIterator::create_and_seek_to_first(level)?,
+            };
+            iter.set_upper_bound(upper);
+            if let Bound::Excluded(key) = lower {
+                while iter.is_valid() && iter.key() == key {
+                    iter.next()?;
+                }
+            }
+            level_iters.push(Box::new(iter));
+        }
+        let level_iter = MergeIterator::create(level_iters);
+
+        TwoMergeIterator::create(
+            TwoMergeIterator::create(memtable_iter, table_iter)?,
+            level_iter,
+        )
+    }
+}
 
-        Ok(FusedIterator::new(LsmIterator::new(
-            iter,
-            map_bound(upper),
-        )?))
+/// Check if the key range of `table` overlaps with the range between `lower` and `upper`.
+pub(crate) fn range_overlap(lower: Bound<&[u8]>, upper: Bound<&[u8]>, table: &SsTable) -> bool {
+    match lower {
+        Bound::Included(key) if key > &table.last_key()[..] => return false,
+        Bound::Excluded(key) if key >= &table.last_key()[..] => return false,
+        _ => {}
+    }
+    match upper {
+        Bound::Included(key) if key < &table.first_key()[..] => return false,
+        Bound::Excluded(key) if key <= &table.first_key()[..] => return false,
+        _ => {}
     }
+    true
 }
@@ -0,0 +1,212 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut};
+use parking_lot::Mutex;
+
+use crate::checksum::crc32;
+
+const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+const SIZEOF_U64: usize = std::mem::size_of::<u64>();
+
+/// Number of records appended after the last rewrite before the manifest is rewritten, unless the
+/// live state itself is larger than this.
+const MANIFEST_REWRITE_THRESHOLD: usize = 1024;
+
+/// A single change to the set of SSTs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ManifestRecord {
+    /// An SST was added to a level. Level 0 is the L0, where SSTs may overlap.
+    AddSst { level: usize, id: usize },
+    /// An SST was removed from whichever level it was in.
+    RemoveSst { id: usize },
+    /// All ids below this one have been allocated.
+    NextSstId(usize),
+}
+
+impl ManifestRecord {
+    const ADD_SST: u8 = 0;
+    const REMOVE_SST: u8 = 1;
+    const NEXT_SST_ID: u8 = 2;
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            ManifestRecord::AddSst { level, id } => {
+                buf.put_u8(Self::ADD_SST);
+                buf.put_u32(*level as u32);
+                buf.put_u64(*id as u64);
+            }
+            ManifestRecord::RemoveSst { id } => {
+                buf.put_u8(Self::REMOVE_SST);
+                buf.put_u64(*id as u64);
+            }
+            ManifestRecord::NextSstId(id) => {
+                buf.put_u8(Self::NEXT_SST_ID);
+                buf.put_u64(*id as u64);
+            }
+        }
+    }
+
+    fn decode(buf: &mut impl Buf) -> Result<Self> {
+        let tag = buf.get_u8();
+        let len = match tag {
+            Self::ADD_SST => SIZEOF_U32 + SIZEOF_U64,
+            Self::REMOVE_SST | Self::NEXT_SST_ID => SIZEOF_U64,
+            tag => bail!("unknown manifest record type {}", tag),
+        };
+        if buf.remaining() < len {
+            bail!("manifest record of type {} is cut short", tag);
+        }
+        let record = match tag {
+            Self::ADD_SST => {
+                let level = buf.get_u32() as usize;
+                let id = buf.get_u64() as usize;
+                ManifestRecord::AddSst { level, id }
+            }
+            Self::REMOVE_SST => ManifestRecord::RemoveSst {
+                id: buf.get_u64() as usize,
+            },
+            _ => ManifestRecord::NextSstId(buf.get_u64() as usize),
+        };
+        Ok(record)
+    }
+}
+
+struct ManifestFile {
+    file: File,
+    /// Number of records appended since the manifest was last rewritten.
+    appended: usize,
+    /// Number of records written by the last rewrite.
+    snapshot_len: usize,
+}
+
+/// An append-only log of the changes to the set of SSTs.
+///
+/// The manifest is a sequence of edits, each encoded as `len (u32) | records | checksum (u32)`,
+/// where the checksum is the CRC-32 of the length and the records. Records of one edit are applied
+/// together, so a compaction that removes some SSTs and adds others is never seen half-done. The
+/// last edit is ignored on recovery if a crash cut it short or tore it, but a bad checksum anywhere
+/// else is reported as corruption.
+pub struct Manifest {
+    file: Mutex<ManifestFile>,
+    path: PathBuf,
+}
+
+impl Manifest {
+    /// Create a new, empty manifest at `path`.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(&path)?;
+        Ok(Self {
+            file: Mutex::new(ManifestFile {
+                file,
+                appended: 0,
+                snapshot_len: 0,
+            }),
+            path,
+        })
+    }
+
+    /// Read all records from the manifest at `path` and reopen it for appending.
+    pub fn recover(path: impl AsRef<Path>) -> Result<(Self, Vec<ManifestRecord>)> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new().read(true).append(true).open(&path)?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        let mut rbuf = &buf[..];
+        let mut records = Vec::new();
+        while rbuf.remaining() >= SIZEOF_U32 {
+            let len = (&rbuf[..SIZEOF_U32]).get_u32() as usize;
+            if rbuf.remaining() < SIZEOF_U32 + len + SIZEOF_U32 {
+                break;
+            }
+            let (edit, rest) = rbuf.split_at(SIZEOF_U32 + len);
+            if crc32(edit) != (&rest[..SIZEOF_U32]).get_u32() {
+                if rest.len() == SIZEOF_U32 {
+                    break;
+                }
+                bail!(
+                    "checksum mismatch in manifest edit at offset {}",
+                    buf.len() - rbuf.len()
+                );
+            }
+            let mut edit = &edit[SIZEOF_U32..];
+            while edit.has_remaining() {
+                records.push(ManifestRecord::decode(&mut edit)?);
+            }
+            rbuf = &rest[SIZEOF_U32..];
+        }
+        let valid_len = buf.len() - rbuf.len();
+        if valid_len < buf.len() {
+            file.set_len(valid_len as u64)?;
+            file.sync_all()?;
+        }
+        let manifest = Self {
+            file: Mutex::new(ManifestFile {
+                file,
+                appended: records.len(),
+                snapshot_len: 0,
+            }),
+            path,
+        };
+        Ok((manifest, records))
+    }
+
+    fn encode_edit(records: &[ManifestRecord]) -> Vec<u8> {
+        let mut buf = vec![0; SIZEOF_U32];
+        for record in records {
+            record.encode(&mut buf);
+        }
+        let len = (buf.len() - SIZEOF_U32) as u32;
+        (&mut buf[..SIZEOF_U32]).put_u32(len);
+        buf.put_u32(crc32(&buf));
+        buf
+    }
+
+    /// Durably append an edit made of `records` to the manifest.
+    pub fn add_records(&self, records: &[ManifestRecord]) -> Result<()> {
+        let buf = Self::encode_edit(records);
+        let mut guard = self.file.lock();
+        guard.file.write_all(&buf)?;
+        guard.file.sync_all()?;
+        guard.appended += records.len();
+        Ok(())
+    }
+
+    /// Check if enough records piled up that the manifest should be rewritten.
+    pub fn needs_rewrite(&self) -> bool {
+        let guard = self.file.lock();
+        guard.appended > guard.snapshot_len.max(MANIFEST_REWRITE_THRESHOLD)
+    }
+
+    /// Atomically replace the manifest with one that contains only `records`, which should
+    /// describe the current state.
+    pub fn rewrite(&self, records: &[ManifestRecord]) -> Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&tmp_path)?;
+        file.write_all(&Self::encode_edit(records))?;
+        file.sync_all()?;
+
+        let mut guard = self.file.lock();
+        std::fs::rename(&tmp_path, &self.path)?;
+        if let Some(parent) = self.path.parent() {
+            File::open(parent)?.sync_all()?;
+        }
+        guard.file = OpenOptions::new().append(true).open(&self.path)?;
+        guard.appended = 0;
+        guard.snapshot_len = records.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
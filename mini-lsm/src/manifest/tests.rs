@@ -0,0 +1,106 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use tempfile::tempdir;
+
+use super::{Manifest, ManifestRecord};
+
+#[test]
+fn test_manifest_recover() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("MANIFEST");
+    let records = vec![
+        ManifestRecord::AddSst { level: 0, id: 1 },
+        ManifestRecord::NextSstId(3),
+        ManifestRecord::RemoveSst { id: 1 },
+        ManifestRecord::AddSst { level: 1, id: 2 },
+    ];
+    {
+        let manifest = Manifest::create(&path).unwrap();
+        manifest.add_records(&records[..2]).unwrap();
+        manifest.add_records(&records[2..]).unwrap();
+    }
+    // Simulate a crash in the middle of appending an edit.
+    {
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0, 0, 0, 9, 0, 0]).unwrap();
+    }
+    {
+        let (manifest, recovered) = Manifest::recover(&path).unwrap();
+        assert_eq!(recovered, records);
+        manifest
+            .add_records(&[ManifestRecord::NextSstId(4)])
+            .unwrap();
+    }
+    let (_, recovered) = Manifest::recover(&path).unwrap();
+    assert_eq!(recovered.len(), records.len() + 1);
+    assert_eq!(recovered.last(), Some(&ManifestRecord::NextSstId(4)));
+}
+
+#[test]
+fn test_manifest_rewrite() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("MANIFEST");
+    {
+        let manifest = Manifest::create(&path).unwrap();
+        for id in 1..=2000 {
+            manifest
+                .add_records(&[
+                    ManifestRecord::AddSst { level: 0, id },
+                    ManifestRecord::RemoveSst { id },
+                ])
+                .unwrap();
+        }
+        assert!(manifest.needs_rewrite());
+        manifest
+            .rewrite(&[ManifestRecord::NextSstId(2001)])
+            .unwrap();
+        assert!(!manifest.needs_rewrite());
+        manifest
+            .add_records(&[ManifestRecord::AddSst { level: 0, id: 2001 }])
+            .unwrap();
+    }
+    let (_, recovered) = Manifest::recover(&path).unwrap();
+    assert_eq!(
+        recovered,
+        vec![
+            ManifestRecord::NextSstId(2001),
+            ManifestRecord::AddSst { level: 0, id: 2001 },
+        ]
+    );
+}
+
+#[test]
+fn test_manifest_recover_torn_edit() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("MANIFEST");
+    let records = vec![
+        ManifestRecord::AddSst { level: 0, id: 1 },
+        ManifestRecord::NextSstId(2),
+    ];
+    {
+        let manifest = Manifest::create(&path).unwrap();
+        manifest.add_records(&records).unwrap();
+    }
+    // Simulate an edit whose length made it to the disk but whose records did not.
+    let edit_len = std::fs::metadata(&path).unwrap().len();
+    {
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0, 0, 0, 9]).unwrap();
+        file.write_all(&[0; 13]).unwrap();
+    }
+    {
+        let (manifest, recovered) = Manifest::recover(&path).unwrap();
+        assert_eq!(recovered, records);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), edit_len);
+        manifest
+            .add_records(&[ManifestRecord::RemoveSst { id: 1 }])
+            .unwrap();
+    }
+
+    // A bad checksum before the last edit is corruption.
+    let mut data = std::fs::read(&path).unwrap();
+    data[5] ^= 1;
+    std::fs::write(&path, &data).unwrap();
+    assert!(Manifest::recover(&path).is_err());
+}
@@ -1,4 +1,6 @@
 use std::ops::Bound;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use anyhow::Result;
@@ -8,11 +10,20 @@ use crossbeam_skiplist::SkipMap;
 use ouroboros::self_referencing;
 
 use crate::iterators::StorageIterator;
+use crate::key::{InternalKey, MAX_SEQ};
 use crate::table::SsTableBuilder;
+use crate::value::ValueKind;
+use crate::wal::{Wal, WalSyncPolicy};
+use crate::write_batch::WriteBatch;
 
-/// A basic mem-table based on crossbeam-skiplist
+/// A basic mem-table based on crossbeam-skiplist. It keeps every version of a key that is written
+/// to it.
 pub struct MemTable {
-    map: Arc<SkipMap<Bytes, Bytes>>,
+    map: Arc<SkipMap<InternalKey, (ValueKind, Bytes)>>,
+    wal: Option<Wal>,
+    id: usize,
+    approximate_size: AtomicUsize,
+    max_seq: AtomicU64,
 }
 
 pub(crate) fn map_bound(bound: Bound<&[u8]>) -> Bound<Bytes> {
@@ -23,32 +34,147 @@ pub(crate) fn map_bound(bound: Bound<&[u8]>) -> Bound<Bytes> {
     }
 }
 
+/// Map a bound of user keys to a bound of internal keys, which includes or excludes all the
+/// versions of the key.
+fn map_internal_bound(bound: Bound<&[u8]>, is_lower: bool) -> Bound<InternalKey> {
+    let internal_key = |key: &[u8], seq| InternalKey::new(Bytes::copy_from_slice(key), seq);
+    match (bound, is_lower) {
+        (Bound::Included(x), true) => Bound::Included(internal_key(x, MAX_SEQ)),
+        (Bound::Excluded(x), true) => Bound::Excluded(internal_key(x, 0)),
+        (Bound::Included(x), false) => Bound::Included(internal_key(x, 0)),
+        (Bound::Excluded(x), false) => Bound::Excluded(internal_key(x, MAX_SEQ)),
+        (Bound::Unbounded, _) => Bound::Unbounded,
+    }
+}
+
 impl MemTable {
     /// Create a new mem-table.
     pub fn create() -> Self {
         Self {
             map: Arc::new(SkipMap::new()),
+            wal: None,
+            id: 0,
+            approximate_size: AtomicUsize::new(0),
+            max_seq: AtomicU64::new(0),
         }
     }
 
-    /// Get a value by key.
-    pub fn get(&self, key: &[u8]) -> Option<Bytes> {
-        self.map.get(key).map(|e| e.value().clone())
+    /// Create a new mem-table whose writes are logged to a new WAL at `path`.
+    pub fn create_with_wal(
+        id: usize,
+        path: impl AsRef<Path>,
+        policy: WalSyncPolicy,
+    ) -> Result<Self> {
+        Ok(Self {
+            map: Arc::new(SkipMap::new()),
+            wal: Some(Wal::create(path, policy)?),
+            id,
+            approximate_size: AtomicUsize::new(0),
+            max_seq: AtomicU64::new(0),
+        })
+    }
+
+    /// Rebuild a mem-table from the WAL at `path`, and keep logging to it.
+    pub fn recover_from_wal(
+        id: usize,
+        path: impl AsRef<Path>,
+        policy: WalSyncPolicy,
+    ) -> Result<Self> {
+        let map = Arc::new(SkipMap::new());
+        let wal = Wal::recover(path, policy, &map)?;
+        let approximate_size = map
+            .iter()
+            .map(|entry| entry.key().key.len() + entry.value().1.len())
+            .sum();
+        let max_seq = map.iter().map(|entry| entry.key().seq).max().unwrap_or(0);
+        Ok(Self {
+            map,
+            wal: Some(wal),
+            id,
+            approximate_size: AtomicUsize::new(approximate_size),
+            max_seq: AtomicU64::new(max_seq),
+        })
     }
 
-    /// Put a key-value pair into the mem-table.
-    pub fn put(&self, key: &[u8], value: &[u8]) {
+    /// Get the newest version of a key whose sequence number is at most `seq`. A delete is
+    /// returned as is, so that it is not mistaken for a missing key.
+    pub fn get(&self, key: &[u8], seq: u64) -> Option<(ValueKind, Bytes)> {
         self.map
-            .insert(Bytes::copy_from_slice(key), Bytes::copy_from_slice(value));
+            .range(InternalKey::new(Bytes::copy_from_slice(key), seq)..)
+            .next()
+            .filter(|entry| entry.key().key == key)
+            .map(|entry| entry.value().clone())
+    }
+
+    /// Put a key-value pair into the mem-table as the version `seq` of the key.
+    pub fn put(&self, key: &[u8], seq: u64, value: &[u8]) -> Result<()> {
+        self.write_batch(WriteBatch::new().put(key, value), seq)
+    }
+
+    /// Delete a key from the mem-table as the version `seq` of the key.
+    pub fn delete(&self, key: &[u8], seq: u64) -> Result<()> {
+        self.write_batch(WriteBatch::new().delete(key), seq)
+    }
+
+    /// Write all entries of a batch into the mem-table as the version `seq` of their keys. The
+    /// batch is appended to the WAL (if any) before it is inserted.
+    pub fn write_batch(&self, batch: &WriteBatch, seq: u64) -> Result<()> {
+        if let Some(ref wal) = self.wal {
+            wal.append(seq, batch)?;
+        }
+        for entry in batch.entries() {
+            self.map.insert(
+                InternalKey::new(entry.key.clone(), seq),
+                (entry.kind, entry.value.clone()),
+            );
+            self.approximate_size
+                .fetch_add(entry.key.len() + entry.value.len(), Ordering::Relaxed);
+        }
+        self.max_seq.fetch_max(seq, Ordering::Relaxed);
+        Ok(())
     }
 
-    /// Get an iterator over a range of keys.
+    /// `fsync` the WAL (if any), so that all the writes to the mem-table so far are durable.
+    pub fn sync_wal(&self) -> Result<()> {
+        match self.wal {
+            Some(ref wal) => wal.sync(),
+            None => Ok(()),
+        }
+    }
+
+    /// Get the approximate size of the keys and values put into the mem-table, in bytes.
+    pub fn approximate_size(&self) -> usize {
+        self.approximate_size.load(Ordering::Relaxed)
+    }
+
+    /// Get the largest sequence number written to the mem-table, or 0 if it is empty.
+    pub fn max_seq(&self) -> u64 {
+        self.max_seq.load(Ordering::Relaxed)
+    }
+
+    /// Get the id of the mem-table, which is also the id of the SST it will be flushed to.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Check if the mem-table has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Get an iterator over all versions of a range of keys.
     pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> MemTableIterator {
-        let (lower, upper) = (map_bound(lower), map_bound(upper));
+        let (lower, upper) = (
+            map_internal_bound(lower, true),
+            map_internal_bound(upper, false),
+        );
         let mut iter = MemTableIteratorBuilder {
             map: self.map.clone(),
             iter_builder: |map| map.range((lower, upper)),
-            item: (Bytes::from_static(&[]), Bytes::from_static(&[])),
+            item: (
+                InternalKey::new(Bytes::from_static(&[]), 0),
+                (ValueKind::Put, Bytes::from_static(&[])),
+            ),
         }
         .build();
         let entry = iter.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next()));
@@ -59,44 +185,67 @@ impl MemTable {
     /// Flush the mem-table to SSTable.
     pub fn flush(&self, builder: &mut SsTableBuilder) -> Result<()> {
         for entry in self.map.iter() {
-            builder.add(&entry.key()[..], &entry.value()[..]);
+            let (kind, value) = entry.value();
+            builder.add_entry(&entry.key().key[..], entry.key().seq, *kind, &value[..]);
         }
         Ok(())
     }
 }
 
-type SkipMapRangeIter<'a> =
-    crossbeam_skiplist::map::Range<'a, Bytes, (Bound<Bytes>, Bound<Bytes>), Bytes, Bytes>;
+type SkipMapRangeIter<'a> = crossbeam_skiplist::map::Range<
+    'a,
+    InternalKey,
+    (Bound<InternalKey>, Bound<InternalKey>),
+    InternalKey,
+    (ValueKind, Bytes),
+>;
 
 /// An iterator over a range of `SkipMap`.
 #[self_referencing]
 pub struct MemTableIterator {
-    map: Arc<SkipMap<Bytes, Bytes>>,
+    map: Arc<SkipMap<InternalKey, (ValueKind, Bytes)>>,
     #[borrows(map)]
     #[not_covariant]
     iter: SkipMapRangeIter<'this>,
-    item: (Bytes, Bytes),
+    item: (InternalKey, (ValueKind, Bytes)),
 }
 
 impl MemTableIterator {
-    fn entry_to_item(entry: Option<Entry<'_, Bytes, Bytes>>) -> (Bytes, Bytes) {
+    fn entry_to_item(
+        entry: Option<Entry<'_, InternalKey, (ValueKind, Bytes)>>,
+    ) -> (InternalKey, (ValueKind, Bytes)) {
         entry
             .map(|x| (x.key().clone(), x.value().clone()))
-            .unwrap_or_else(|| (Bytes::from_static(&[]), Bytes::from_static(&[])))
+            .unwrap_or_else(|| {
+                (
+                    InternalKey::new(Bytes::from_static(&[]), 0),
+                    (ValueKind::Put, Bytes::from_static(&[])),
+                )
+            })
     }
 }
 
 impl StorageIterator for MemTableIterator {
     fn value(&self) -> &[u8] {
-        &self.borrow_item().1[..]
+        let (_, (_, value)) = self.borrow_item();
+        &value[..]
+    }
+
+    fn kind(&self) -> ValueKind {
+        let (_, (kind, _)) = self.borrow_item();
+        *kind
+    }
+
+    fn seq(&self) -> u64 {
+        self.borrow_item().0.seq
     }
 
     fn key(&self) -> &[u8] {
-        &self.borrow_item().0[..]
+        &self.borrow_item().0.key[..]
     }
 
     fn is_valid(&self) -> bool {
-        !self.borrow_item().0.is_empty()
+        !self.borrow_item().0.key.is_empty()
     }
 
     fn next(&mut self) -> Result<()> {
@@ -1,40 +1,43 @@
+use bytes::Bytes;
 use tempfile::tempdir;
 
 use super::MemTable;
 use crate::iterators::StorageIterator;
+use crate::key::MAX_SEQ;
 use crate::table::{SsTableBuilder, SsTableIterator};
+use crate::value::ValueKind;
 
 #[test]
 fn test_memtable_get() {
     let memtable = MemTable::create();
-    memtable.put(b"key1", b"value1");
-    memtable.put(b"key2", b"value2");
-    memtable.put(b"key3", b"value3");
-    assert_eq!(&memtable.get(b"key1").unwrap()[..], b"value1");
-    assert_eq!(&memtable.get(b"key2").unwrap()[..], b"value2");
-    assert_eq!(&memtable.get(b"key3").unwrap()[..], b"value3");
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    memtable.put(b"key3", 3, b"value3").unwrap();
+    assert_eq!(&memtable.get(b"key1", MAX_SEQ).unwrap().1[..], b"value1");
+    assert_eq!(&memtable.get(b"key2", MAX_SEQ).unwrap().1[..], b"value2");
+    assert_eq!(&memtable.get(b"key3", MAX_SEQ).unwrap().1[..], b"value3");
 }
 
 #[test]
 fn test_memtable_overwrite() {
     let memtable = MemTable::create();
-    memtable.put(b"key1", b"value1");
-    memtable.put(b"key2", b"value2");
-    memtable.put(b"key3", b"value3");
-    memtable.put(b"key1", b"value11");
-    memtable.put(b"key2", b"value22");
-    memtable.put(b"key3", b"value33");
-    assert_eq!(&memtable.get(b"key1").unwrap()[..], b"value11");
-    assert_eq!(&memtable.get(b"key2").unwrap()[..], b"value22");
-    assert_eq!(&memtable.get(b"key3").unwrap()[..], b"value33");
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    memtable.put(b"key3", 3, b"value3").unwrap();
+    memtable.put(b"key1", 4, b"value11").unwrap();
+    memtable.put(b"key2", 5, b"value22").unwrap();
+    memtable.put(b"key3", 6, b"value33").unwrap();
+    assert_eq!(&memtable.get(b"key1", MAX_SEQ).unwrap().1[..], b"value11");
+    assert_eq!(&memtable.get(b"key2", MAX_SEQ).unwrap().1[..], b"value22");
+    assert_eq!(&memtable.get(b"key3", MAX_SEQ).unwrap().1[..], b"value33");
 }
 
 #[test]
 fn test_memtable_flush() {
     let memtable = MemTable::create();
-    memtable.put(b"key1", b"value1");
-    memtable.put(b"key2", b"value2");
-    memtable.put(b"key3", b"value3");
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    memtable.put(b"key3", 3, b"value3").unwrap();
     let mut builder = SsTableBuilder::new(128);
     memtable.flush(&mut builder).unwrap();
     let dir = tempdir().unwrap();
@@ -56,9 +59,9 @@ fn test_memtable_flush() {
 fn test_memtable_iter() {
     use std::ops::Bound;
     let memtable = MemTable::create();
-    memtable.put(b"key1", b"value1");
-    memtable.put(b"key2", b"value2");
-    memtable.put(b"key3", b"value3");
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    memtable.put(b"key3", 3, b"value3").unwrap();
 
     {
         let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
@@ -93,3 +96,83 @@ fn test_memtable_iter() {
         assert!(!iter.is_valid());
     }
 }
+
+#[test]
+fn test_memtable_approximate_size() {
+    let memtable = MemTable::create();
+    assert_eq!(memtable.approximate_size(), 0);
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    assert_eq!(memtable.approximate_size(), 10);
+    memtable.put(b"key1", 2, b"value11").unwrap();
+    memtable.put(b"key2", 3, b"").unwrap();
+    assert_eq!(memtable.approximate_size(), 25);
+}
+
+#[test]
+fn test_memtable_delete_and_empty_value() {
+    use std::ops::Bound;
+    let memtable = MemTable::create();
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.put(b"key2", 2, b"").unwrap();
+    memtable.delete(b"key1", 3).unwrap();
+    memtable.delete(b"key3", 4).unwrap();
+    assert_eq!(
+        memtable.get(b"key1", MAX_SEQ),
+        Some((ValueKind::Delete, Bytes::new()))
+    );
+    assert_eq!(
+        memtable.get(b"key2", MAX_SEQ),
+        Some((ValueKind::Put, Bytes::new()))
+    );
+    assert_eq!(
+        memtable.get(b"key3", MAX_SEQ),
+        Some((ValueKind::Delete, Bytes::new()))
+    );
+    let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+    for (key, seq, kind, value) in [
+        (b"key1", 3, ValueKind::Delete, &b""[..]),
+        (b"key1", 1, ValueKind::Put, b"value1"),
+        (b"key2", 2, ValueKind::Put, b""),
+        (b"key3", 4, ValueKind::Delete, b""),
+    ] {
+        assert_eq!(iter.key(), key);
+        assert_eq!(iter.seq(), seq);
+        assert_eq!(iter.kind(), kind);
+        assert_eq!(iter.value(), value);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_memtable_versions() {
+    use std::ops::Bound;
+    let memtable = MemTable::create();
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    memtable.put(b"key1", 3, b"value11").unwrap();
+    memtable.delete(b"key2", 4).unwrap();
+    assert_eq!(memtable.get(b"key1", 0), None);
+    assert_eq!(&memtable.get(b"key1", 1).unwrap().1[..], b"value1");
+    assert_eq!(&memtable.get(b"key1", 2).unwrap().1[..], b"value1");
+    assert_eq!(&memtable.get(b"key1", 3).unwrap().1[..], b"value11");
+    assert_eq!(&memtable.get(b"key2", 3).unwrap().1[..], b"value2");
+    assert_eq!(memtable.get(b"key2", 4).unwrap().0, ValueKind::Delete);
+    assert_eq!(memtable.max_seq(), 4);
+
+    // The bounds include or exclude all versions of a key.
+    let mut iter = memtable.scan(Bound::Included(b"key1"), Bound::Excluded(b"key2"));
+    for seq in [3, 1] {
+        assert_eq!(iter.key(), b"key1");
+        assert_eq!(iter.seq(), seq);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    let mut iter = memtable.scan(Bound::Excluded(b"key1"), Bound::Included(b"key2"));
+    for seq in [4, 2] {
+        assert_eq!(iter.key(), b"key2");
+        assert_eq!(iter.seq(), seq);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use bytes::{BufMut, Bytes};
+
+/// Combines the operands written by `LsmStorage::merge` with the value of their key, set through
+/// `LsmStorageOptions::merge_operator`.
+///
+/// The operands are kept as they are written, and only combined when the key is read or
+/// compacted. The same operands may be combined more than once, so the result must only depend on
+/// the arguments.
+pub trait MergeOperator: std::fmt::Debug + Send + Sync {
+    /// Apply `operands`, from the oldest to the newest, to the `existing` value of `key`, which is
+    /// `None` if the key does not exist.
+    fn merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[Bytes]) -> Result<Bytes>;
+
+    /// Combine `operands` of `key`, from the oldest to the newest, into one operand that has the
+    /// same effect, so that several merges of a key in a `WriteBatch` can be written as one. Fails
+    /// by default, so that such a batch cannot be written.
+    fn partial_merge(&self, key: &[u8], operands: &[Bytes]) -> Result<Bytes> {
+        let _ = operands;
+        bail!(
+            "merge operator cannot combine the operands of key {:?}",
+            Bytes::copy_from_slice(key)
+        )
+    }
+}
+
+/// Apply the operands of `key`, collected from the newest to the oldest, to `existing`.
+pub(crate) fn resolve_operands(
+    operator: Option<&Arc<dyn MergeOperator>>,
+    key: &[u8],
+    existing: Option<&[u8]>,
+    mut operands: Vec<Bytes>,
+) -> Result<Bytes> {
+    let Some(operator) = operator else {
+        bail!(
+            "key {:?} has merge operands but no merge operator is set",
+            Bytes::copy_from_slice(key)
+        );
+    };
+    operands.reverse();
+    operator.merge(key, existing, &operands)
+}
+
+const SIZEOF_U64: usize = std::mem::size_of::<u64>();
+
+/// Adds up the operands as counters. The value and the operands are 8-byte big-endian numbers, and
+/// a missing value counts as 0. The sum wraps around on overflow.
+#[derive(Debug, Default)]
+pub struct U64AddOperator;
+
+impl U64AddOperator {
+    fn decode(key: &[u8], value: &[u8]) -> Result<u64> {
+        match <[u8; SIZEOF_U64]>::try_from(value) {
+            Ok(value) => Ok(u64::from_be_bytes(value)),
+            Err(_) => bail!(
+                "value of key {:?} is {} bytes, not a u64",
+                Bytes::copy_from_slice(key),
+                value.len()
+            ),
+        }
+    }
+}
+
+impl MergeOperator for U64AddOperator {
+    fn merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[Bytes]) -> Result<Bytes> {
+        let mut sum = match existing {
+            Some(value) => Self::decode(key, value)?,
+            None => 0,
+        };
+        for operand in operands {
+            sum = sum.wrapping_add(Self::decode(key, operand)?);
+        }
+        Ok(Bytes::copy_from_slice(&sum.to_be_bytes()))
+    }
+
+    fn partial_merge(&self, key: &[u8], operands: &[Bytes]) -> Result<Bytes> {
+        // Adding the operands to 0 gives their sum.
+        self.merge(key, None, operands)
+    }
+}
+
+/// Appends the operands to the value, which is empty if the key does not exist.
+#[derive(Debug, Default)]
+pub struct AppendOperator;
+
+impl MergeOperator for AppendOperator {
+    fn merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[Bytes]) -> Result<Bytes> {
+        let existing = existing.unwrap_or_default();
+        let len = existing.len() + operands.iter().map(|operand| operand.len()).sum::<usize>();
+        let mut value = Vec::with_capacity(len);
+        value.put_slice(existing);
+        for operand in operands {
+            value.put_slice(operand);
+        }
+        Ok(value.into())
+    }
+
+    fn partial_merge(&self, key: &[u8], operands: &[Bytes]) -> Result<Bytes> {
+        // Appending the operands to an empty value gives their concatenation.
+        self.merge(key, None, operands)
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::lsm_storage::{LsmStorageCore, LsmStorageInner};
+
+/// The sequence numbers of the live snapshots, with the number of snapshots at each.
+#[derive(Default)]
+pub(crate) struct SnapshotList {
+    seqs: Mutex<BTreeMap<u64, usize>>,
+}
+
+impl SnapshotList {
+    /// Register a snapshot at the sequence number returned by `get_seq`, which is called with the
+    /// list locked.
+    pub(crate) fn register(&self, get_seq: impl FnOnce() -> u64) -> u64 {
+        let mut seqs = self.seqs.lock();
+        let seq = get_seq();
+        *seqs.entry(seq).or_insert(0) += 1;
+        seq
+    }
+
+    pub(crate) fn release(&self, seq: u64) {
+        let mut seqs = self.seqs.lock();
+        if let Some(count) = seqs.get_mut(&seq) {
+            *count -= 1;
+            if *count == 0 {
+                seqs.remove(&seq);
+            }
+        }
+    }
+
+    /// Get the sequence number of the oldest live snapshot.
+    pub(crate) fn oldest(&self) -> Option<u64> {
+        self.seqs.lock().keys().next().copied()
+    }
+
+    /// Get the sequence numbers of the live snapshots, from the oldest to the newest.
+    pub(crate) fn seqs(&self) -> Vec<u64> {
+        self.seqs.lock().keys().copied().collect()
+    }
+}
+
+/// A consistent view of the storage at the time it was taken.
+///
+/// The snapshot pins its sequence number and the memtables and SSTs of the storage, so that its
+/// reads see neither the writes after it nor the effects of later flushes and compactions.
+/// Compaction keeps the versions the snapshot can see until it is dropped.
+pub struct Snapshot {
+    core: Arc<LsmStorageCore>,
+    state: Arc<LsmStorageInner>,
+    seq: u64,
+}
+
+impl Snapshot {
+    pub(crate) fn new(core: Arc<LsmStorageCore>) -> Self {
+        // A compaction that read the list before the snapshot is registered only compacts versions
+        // up to its sequence number. The state is taken after the sequence number, so that it has
+        // all the writes up to it.
+        let seq = core.snapshots.register(|| core.latest_seq());
+        let state = core.inner.read().clone();
+        Self { core, state, seq }
+    }
+
+    /// Get the sequence number the snapshot reads at.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Get a key as of the snapshot.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.core.get_from_state(&self.state, key, self.seq)
+    }
+
+    /// Create an iterator over a range of keys as of the snapshot.
+    pub fn scan(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.core
+            .scan_from_state(&self.state, lower, upper, self.seq)
+    }
+}
+
+impl Clone for Snapshot {
+    fn clone(&self) -> Self {
+        self.core.snapshots.register(|| self.seq);
+        Self {
+            core: self.core.clone(),
+            state: self.state.clone(),
+            seq: self.seq,
+        }
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.core.snapshots.release(self.seq);
+    }
+}
@@ -1,17 +1,63 @@
+mod bloom;
 mod builder;
+mod footer;
 mod iterator;
+mod properties;
 
 use std::fs::File;
+use std::io::Write;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::UNIX_EPOCH;
 
 use anyhow::{anyhow, Result};
-pub use builder::SsTableBuilder;
+pub use bloom::Bloom;
+pub use builder::{SsTableBuilder, DEFAULT_BLOOM_BITS_PER_KEY};
 use bytes::{Buf, BufMut, Bytes};
+use footer::{Footer, SectionHandle, FORMAT_V0, FORMAT_V1, FORMAT_V2};
 pub use iterator::SsTableIterator;
+pub use properties::TableProperties;
 
-use crate::block::Block;
+use crate::block::{Block, BlockIterator};
+use crate::checksum::crc32;
 use crate::lsm_storage::BlockCache;
+use crate::value::ValueKind;
+use crate::varint::{get_varint, put_varint, varint_len};
+
+const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+
+/// The content of an SST does not match its checksum, or cannot be decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptionError {
+    /// Id of the SST.
+    pub sst_id: usize,
+    /// Index of the corrupted data block, or `None` if the file is not an SST, or its block metas,
+    /// bloom filter or footer are corrupted.
+    pub block_idx: Option<usize>,
+}
+
+impl std::fmt::Display for CorruptionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.block_idx {
+            Some(block_idx) => write!(f, "SST {} is corrupted in block {}", self.sst_id, block_idx),
+            None => write!(f, "SST {} is corrupted in its metadata", self.sst_id),
+        }
+    }
+}
+
+impl std::error::Error for CorruptionError {}
+
+/// Split the checksum off the end of `data`, and return the rest if it matches.
+fn verify_checksum(data: &[u8]) -> Option<&[u8]> {
+    if data.len() < SIZEOF_U32 {
+        return None;
+    }
+    let (data, checksum) = data.split_at(data.len() - SIZEOF_U32);
+    if crc32(data) != (&checksum[..]).get_u32() {
+        return None;
+    }
+    Some(data)
+}
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BlockMeta {
@@ -27,9 +73,9 @@ impl BlockMeta {
         let mut estimated_size = 0;
         for meta in block_meta {
             // The size of offset
-            estimated_size += std::mem::size_of::<u32>();
+            estimated_size += std::mem::size_of::<u64>();
             // The size of key length
-            estimated_size += std::mem::size_of::<u16>();
+            estimated_size += varint_len(meta.first_key.len() as u64);
             // The size of actual key
             estimated_size += meta.first_key.len();
         }
@@ -37,8 +83,8 @@ impl BlockMeta {
         buf.reserve(estimated_size);
         let original_len = buf.len();
         for meta in block_meta {
-            buf.put_u32(meta.offset as u32);
-            buf.put_u16(meta.first_key.len() as u16);
+            buf.put_u64(meta.offset as u64);
+            put_varint(buf, meta.first_key.len() as u64);
             buf.put_slice(&meta.first_key);
         }
         assert_eq!(estimated_size, buf.len() - original_len);
@@ -46,6 +92,18 @@ impl BlockMeta {
 
     /// Decode block meta from a buffer.
     pub fn decode_block_meta(mut buf: impl Buf) -> Vec<BlockMeta> {
+        let mut block_meta = Vec::new();
+        while buf.has_remaining() {
+            let offset = buf.get_u64() as usize;
+            let first_key_len = get_varint(&mut buf) as usize;
+            let first_key = buf.copy_to_bytes(first_key_len);
+            block_meta.push(BlockMeta { offset, first_key });
+        }
+        block_meta
+    }
+
+    /// Decode block meta written in the first SST format, with u32 offsets and u16 key lengths.
+    pub fn decode_block_meta_v0(mut buf: impl Buf) -> Vec<BlockMeta> {
         let mut block_meta = Vec::new();
         while buf.has_remaining() {
             let offset = buf.get_u32() as usize;
@@ -97,15 +155,19 @@ impl FileObject {
 
     /// Create a new file object (day 2) and write the file to the disk (day 4).
     pub fn create(path: &Path, data: Vec<u8>) -> Result<Self> {
-        std::fs::write(path, &data)?;
+        let mut file = File::create(path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
         Ok(FileObject(
             File::options().read(true).write(false).open(path)?,
             data.len() as u64,
         ))
     }
 
-    pub fn open(_path: &Path) -> Result<Self> {
-        unimplemented!()
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::options().read(true).write(false).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(FileObject(file, size))
     }
 }
 
@@ -115,6 +177,9 @@ pub struct SsTable {
     block_meta_offset: usize,
     id: usize,
     block_cache: Option<Arc<BlockCache>>,
+    properties: TableProperties,
+    bloom: Bloom,
+    format_version: u32,
 }
 
 impl SsTable {
@@ -124,18 +189,98 @@ impl SsTable {
     }
 
     /// Open SSTable from a file.
+    ///
+    /// The file is laid out as `data blocks | index | filter | meta | footer`. The index holds the
+    /// block metas, and the filter is a bloom filter. Each data block and section is followed by
+    /// its checksum (u32). The footer has a fixed size and ends with a magic number. It holds the
+    /// format version, and the offsets and lengths of the sections, so that sections can be added
+    /// in later versions. Files of the older formats, whose footers had no magic number, can still
+    /// be read.
     pub fn open(id: usize, block_cache: Option<Arc<BlockCache>>, file: FileObject) -> Result<Self> {
-        let len = file.size();
-        let raw_meta_offset = file.read(len - 4, 4)?;
-        let block_meta_offset = (&raw_meta_offset[..]).get_u32() as u64;
-        let raw_meta = file.read(block_meta_offset, len - 4 - block_meta_offset)?;
-        Ok(Self {
+        let corrupted = || CorruptionError {
+            sst_id: id,
+            block_idx: None,
+        };
+        let footer = Footer::read(&file, id)?.ok_or_else(corrupted)?;
+        let raw_filter = Self::read_section(&file, footer.filter)?.ok_or_else(corrupted)?;
+        let bloom = if raw_filter.is_empty() {
+            // Without a filter, any key may be in the SST.
+            Bloom::build_from_key_hashes(&[], 0)
+        } else {
+            Bloom::decode(&raw_filter)?
+        };
+        let raw_index = Self::read_section(&file, footer.index)?.ok_or_else(corrupted)?;
+        let block_metas = match footer.version {
+            FORMAT_V0 => BlockMeta::decode_block_meta_v0(&raw_index[..]),
+            _ => BlockMeta::decode_block_meta(&raw_index[..]),
+        };
+        if block_metas.is_empty() {
+            return Err(corrupted().into());
+        }
+        let raw_properties = Self::read_section(&file, footer.meta)?.ok_or_else(corrupted)?;
+        let mut table = Self {
             file,
-            block_metas: BlockMeta::decode_block_meta(&raw_meta[..]),
-            block_meta_offset: block_meta_offset as usize,
+            block_metas,
+            block_meta_offset: footer.index.offset as usize,
             id,
             block_cache,
-        })
+            properties: TableProperties::default(),
+            bloom,
+            format_version: footer.version,
+        };
+        table.properties = if raw_properties.is_empty() {
+            // Older formats have no properties, so compute them from the data blocks.
+            table.compute_properties()?
+        } else {
+            TableProperties::decode(&raw_properties).map_err(|_| corrupted())?
+        };
+        Ok(table)
+    }
+
+    /// Compute the properties of an SST by reading all of its data blocks. The creation time is
+    /// taken from the file.
+    fn compute_properties(&self) -> Result<TableProperties> {
+        let creation_time = self
+            .file
+            .0
+            .metadata()?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let mut properties = TableProperties {
+            first_key: self.first_key().clone(),
+            creation_time,
+            ..Default::default()
+        };
+        for block_idx in 0..self.num_of_blocks() {
+            let mut blk_iter = BlockIterator::create_and_seek_to_first(self.read_block(block_idx)?);
+            while blk_iter.is_valid() {
+                properties.num_entries += 1;
+                if blk_iter.kind() == ValueKind::Delete {
+                    properties.num_deletions += 1;
+                }
+                properties.raw_key_size += blk_iter.key().len() as u64;
+                properties.raw_value_size += blk_iter.value().len() as u64;
+                properties.last_key = Bytes::copy_from_slice(blk_iter.key());
+                properties.max_seq = properties.max_seq.max(blk_iter.seq());
+                blk_iter.next();
+            }
+        }
+        Ok(properties)
+    }
+
+    /// Read a section and verify its checksum. Returns `None` if the checksum does not match.
+    fn read_section(file: &FileObject, handle: SectionHandle) -> Result<Option<Vec<u8>>> {
+        if handle.len == 0 {
+            return Ok(Some(Vec::new()));
+        }
+        let mut data = file.read(handle.offset, handle.len)?;
+        let len = match verify_checksum(&data) {
+            Some(section) => section.len(),
+            None => return Ok(None),
+        };
+        data.truncate(len);
+        Ok(Some(data))
     }
 
     /// Read a block from the disk.
@@ -148,7 +293,16 @@ impl SsTable {
         let block_data = self
             .file
             .read(offset as u64, (offset_end - offset) as u64)?;
-        Ok(Arc::new(Block::decode(&block_data[..])))
+        let block_data = verify_checksum(&block_data).ok_or(CorruptionError {
+            sst_id: self.id,
+            block_idx: Some(block_idx),
+        })?;
+        let block = match self.format_version {
+            FORMAT_V0 => Block::decode_v0(block_data),
+            FORMAT_V1 | FORMAT_V2 => Block::decode_v1(block_data),
+            _ => Block::decode(block_data),
+        };
+        Ok(Arc::new(block))
     }
 
     /// Read a block from disk, with block cache.
@@ -156,24 +310,95 @@ impl SsTable {
         if let Some(ref block_cache) = self.block_cache {
             let blk = block_cache
                 .try_get_with((self.id, block_idx), || self.read_block(block_idx))
-                .map_err(|e| anyhow!("{}", e))?;
+                .map_err(|e| match e.downcast_ref::<CorruptionError>() {
+                    Some(e) => e.clone().into(),
+                    None => anyhow!("{}", e),
+                })?;
             Ok(blk)
         } else {
             self.read_block(block_idx)
         }
     }
 
-    /// Find the block that may contain `key`.
+    /// Find the block that may contain the newest version of `key`. The versions of a key may
+    /// span several blocks, so it is the last block whose first key is < `key`.
     pub fn find_block_idx(&self, key: &[u8]) -> usize {
         self.block_metas
-            .partition_point(|meta| meta.first_key <= key)
+            .partition_point(|meta| meta.first_key < key)
             .saturating_sub(1)
     }
 
+    /// Look up the newest version of `key` whose sequence number is at most `seq`. A delete is
+    /// returned as is, so that it is not mistaken for a missing key.
+    pub fn get(&self, key: &[u8], seq: u64) -> Result<Option<(ValueKind, Bytes)>> {
+        if key < &self.first_key()[..] || key > &self.last_key()[..] {
+            return Ok(None);
+        }
+        let mut blk_idx = self.find_block_idx(key);
+        let mut blk_iter =
+            BlockIterator::create_and_seek_to_key(self.read_block_cached(blk_idx)?, key);
+        loop {
+            if !blk_iter.is_valid() {
+                blk_idx += 1;
+                if blk_idx >= self.num_of_blocks() || self.block_metas[blk_idx].first_key != key {
+                    return Ok(None);
+                }
+                blk_iter =
+                    BlockIterator::create_and_seek_to_first(self.read_block_cached(blk_idx)?);
+                continue;
+            }
+            if blk_iter.key() != key {
+                return Ok(None);
+            }
+            if blk_iter.seq() <= seq {
+                return Ok(Some((
+                    blk_iter.kind(),
+                    Bytes::copy_from_slice(blk_iter.value()),
+                )));
+            }
+            blk_iter.next();
+        }
+    }
+
+    /// Check if the SST has a bloom filter.
+    pub fn has_bloom_filter(&self) -> bool {
+        !self.bloom.is_empty()
+    }
+
+    /// Check the bloom filter for `key`. If it returns false, the key is not in the SST.
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.bloom.may_contain(Bloom::hash(key))
+    }
+
     /// Get number of data blocks.
     pub fn num_of_blocks(&self) -> usize {
         self.block_metas.len()
     }
+
+    /// Get the first key of the SST.
+    pub fn first_key(&self) -> &Bytes {
+        &self.block_metas[0].first_key
+    }
+
+    /// Get the last key of the SST.
+    pub fn last_key(&self) -> &Bytes {
+        &self.properties.last_key
+    }
+
+    /// Get the properties of the SST.
+    pub fn properties(&self) -> &TableProperties {
+        &self.properties
+    }
+
+    /// Get the size of the SST file in bytes.
+    pub fn table_size(&self) -> u64 {
+        self.file.size()
+    }
+
+    /// Get the id of the SST.
+    pub fn id(&self) -> usize {
+        self.id
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,111 @@
+use anyhow::{ensure, Result};
+use bytes::{BufMut, Bytes};
+
+/// A bloom filter over the keys of an SST, using the double hashing scheme of LevelDB.
+///
+/// The filter is encoded as its bit array followed by the number of hash functions (u8). An empty
+/// filter may contain any key.
+pub struct Bloom {
+    /// The bit array of the filter.
+    filter: Bytes,
+    /// Number of hash functions.
+    k: u8,
+}
+
+impl Bloom {
+    /// Hash a key for [`Bloom::build_from_key_hashes`] and [`Bloom::may_contain`]. This is the
+    /// hash function of LevelDB.
+    pub fn hash(key: &[u8]) -> u32 {
+        const SEED: u32 = 0xbc9f1d34;
+        const M: u32 = 0xc6a4a793;
+        let mut h = SEED ^ (key.len() as u32).wrapping_mul(M);
+        let mut chunks = key.chunks_exact(4);
+        for chunk in &mut chunks {
+            let w = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            h = h.wrapping_add(w);
+            h = h.wrapping_mul(M);
+            h ^= h >> 16;
+        }
+        let rest = chunks.remainder();
+        if !rest.is_empty() {
+            for (i, b) in rest.iter().enumerate() {
+                h = h.wrapping_add((*b as u32) << (8 * i));
+            }
+            h = h.wrapping_mul(M);
+            h ^= h >> 24;
+        }
+        h
+    }
+
+    /// Check if there is no filter, so that any key may be contained.
+    pub fn is_empty(&self) -> bool {
+        self.filter.is_empty()
+    }
+
+    /// Build a filter with `bits_per_key` bits for each key. No filter is built if `bits_per_key`
+    /// is 0.
+    pub fn build_from_key_hashes(key_hashes: &[u32], bits_per_key: usize) -> Self {
+        if bits_per_key == 0 || key_hashes.is_empty() {
+            return Self {
+                filter: Bytes::new(),
+                k: 0,
+            };
+        }
+        // ln(2) * bits_per_key hash functions minimize the false positive rate.
+        let k = ((bits_per_key as f64 * 0.69) as u8).clamp(1, 30);
+        // Use at least 64 bits so that small filters are not too inaccurate.
+        let nbits = (key_hashes.len() * bits_per_key).max(64);
+        let nbytes = (nbits + 7) / 8;
+        let nbits = nbytes * 8;
+        let mut filter = vec![0u8; nbytes];
+        for h in key_hashes {
+            let mut h = *h;
+            let delta = h.rotate_left(15);
+            for _ in 0..k {
+                let bit = h as usize % nbits;
+                filter[bit / 8] |= 1 << (bit % 8);
+                h = h.wrapping_add(delta);
+            }
+        }
+        Self {
+            filter: filter.into(),
+            k,
+        }
+    }
+
+    /// Check if the key with hash `h` may be in the filter.
+    pub fn may_contain(&self, mut h: u32) -> bool {
+        if self.filter.is_empty() {
+            return true;
+        }
+        let nbits = self.filter.len() * 8;
+        let delta = h.rotate_left(15);
+        for _ in 0..self.k {
+            let bit = h as usize % nbits;
+            if self.filter[bit / 8] & (1 << (bit % 8)) == 0 {
+                return false;
+            }
+            h = h.wrapping_add(delta);
+        }
+        true
+    }
+
+    /// Encode the filter to a buffer.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.filter);
+        buf.put_u8(self.k);
+    }
+
+    /// Decode a filter from a buffer.
+    pub fn decode(buf: &[u8]) -> Result<Self> {
+        ensure!(!buf.is_empty(), "bloom filter is empty");
+        let k = buf[buf.len() - 1];
+        Ok(Self {
+            filter: Bytes::copy_from_slice(&buf[..buf.len() - 1]),
+            k,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests;
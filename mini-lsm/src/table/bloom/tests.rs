@@ -0,0 +1,45 @@
+use super::Bloom;
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn build(num_keys: usize, bits_per_key: usize) -> Bloom {
+    let hashes: Vec<u32> = (0..num_keys).map(|i| Bloom::hash(&key_of(i))).collect();
+    let mut buf = Vec::new();
+    Bloom::build_from_key_hashes(&hashes, bits_per_key).encode(&mut buf);
+    Bloom::decode(&buf).unwrap()
+}
+
+#[test]
+fn test_bloom_no_false_negative() {
+    let bloom = build(1000, 10);
+    for i in 0..1000 {
+        assert!(bloom.may_contain(Bloom::hash(&key_of(i))));
+    }
+}
+
+#[test]
+fn test_bloom_false_positive_rate() {
+    let bloom = build(1000, 10);
+    let false_positives = (1000..11000)
+        .filter(|i| bloom.may_contain(Bloom::hash(&key_of(*i))))
+        .count();
+    // The expected rate is about 1%.
+    assert!(false_positives < 300, "{} false positives", false_positives);
+}
+
+#[test]
+fn test_bloom_disabled() {
+    let bloom = build(100, 0);
+    for i in 0..1000 {
+        assert!(bloom.may_contain(Bloom::hash(&key_of(i))));
+    }
+}
+
+#[test]
+fn test_bloom_hash() {
+    assert_ne!(Bloom::hash(b""), Bloom::hash(b"a"));
+    assert_ne!(Bloom::hash(b"key_1"), Bloom::hash(b"key_2"));
+    assert_ne!(Bloom::hash(b"abcd"), Bloom::hash(b"abcde"));
+}
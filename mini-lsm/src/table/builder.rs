@@ -1,20 +1,31 @@
 use std::path::Path;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::Result;
 use bytes::BufMut;
 
-use super::{BlockMeta, FileObject, SsTable};
+use super::footer::{Footer, SectionHandle, FORMAT_VERSION};
+use super::{BlockMeta, Bloom, FileObject, SsTable, TableProperties};
 use crate::block::BlockBuilder;
+use crate::checksum::crc32;
 use crate::lsm_storage::BlockCache;
+use crate::value::ValueKind;
+
+/// Number of bloom filter bits for each key, which gives a false positive rate of about 1%.
+pub const DEFAULT_BLOOM_BITS_PER_KEY: usize = 10;
 
 /// Builds an SSTable from key-value pairs.
 pub struct SsTableBuilder {
     builder: BlockBuilder,
     first_key: Vec<u8>,
+    last_key: Vec<u8>,
+    properties: TableProperties,
     data: Vec<u8>,
     pub(super) meta: Vec<BlockMeta>,
     block_size: usize,
+    key_hashes: Vec<u32>,
+    bloom_bits_per_key: usize,
 }
 
 impl SsTableBuilder {
@@ -24,25 +35,53 @@ impl SsTableBuilder {
             data: Vec::new(),
             meta: Vec::new(),
             first_key: Vec::new(),
+            last_key: Vec::new(),
+            properties: TableProperties::default(),
             block_size,
             builder: BlockBuilder::new(block_size),
+            key_hashes: Vec::new(),
+            bloom_bits_per_key: DEFAULT_BLOOM_BITS_PER_KEY,
         }
     }
 
+    /// Set the number of bloom filter bits for each key. No filter is built if it is 0.
+    pub fn with_bloom_bits_per_key(mut self, bits_per_key: usize) -> Self {
+        self.bloom_bits_per_key = bits_per_key;
+        self
+    }
+
     /// Adds a key-value pair to SSTable
     pub fn add(&mut self, key: &[u8], value: &[u8]) {
+        self.add_entry(key, 0, ValueKind::Put, value)
+    }
+
+    /// Adds a version of a key to SSTable. The versions of a key must be added from the newest to
+    /// the oldest.
+    pub fn add_entry(&mut self, key: &[u8], seq: u64, kind: ValueKind, value: &[u8]) {
         if self.first_key.is_empty() {
             self.first_key = key.to_vec();
         }
+        if self.last_key != key {
+            self.key_hashes.push(Bloom::hash(key));
+            self.last_key.clear();
+            self.last_key.extend_from_slice(key);
+        }
+        self.properties.num_entries += 1;
+        if kind == ValueKind::Delete {
+            self.properties.num_deletions += 1;
+        }
+        self.properties.raw_key_size += key.len() as u64;
+        self.properties.raw_value_size += value.len() as u64;
+        self.properties.max_seq = self.properties.max_seq.max(seq);
 
-        if self.builder.add(key, value) {
+        if self.builder.add_entry(key, seq, kind, value) {
             return;
         }
         // create a new block builder and append block data
         self.finish_block();
 
         // add the key-value pair to the next block
-        assert!(self.builder.add(key, value));
+        assert!(self.builder.add_entry(key, seq, kind, value));
         self.first_key = key.to_vec();
     }
 
@@ -51,6 +90,11 @@ impl SsTableBuilder {
         self.data.len()
     }
 
+    /// Check if no key-value pair has been added.
+    pub fn is_empty(&self) -> bool {
+        self.meta.is_empty() && self.builder.is_empty()
+    }
+
     fn finish_block(&mut self) {
         let builder = std::mem::replace(&mut self.builder, BlockBuilder::new(self.block_size));
         let encoded_block = builder.build().encode();
@@ -58,7 +102,8 @@ impl SsTableBuilder {
             offset: self.data.len(),
             first_key: std::mem::take(&mut self.first_key).into(),
         });
-        self.data.extend(encoded_block);
+        self.data.extend(&encoded_block);
+        self.data.put_u32(crc32(&encoded_block));
     }
 
     /// Builds the SSTable and writes it to the given path. No need to actually write to disk until
@@ -72,8 +117,25 @@ impl SsTableBuilder {
         self.finish_block();
         let mut buf = self.data;
         let meta_offset = buf.len();
-        BlockMeta::encode_block_meta(&self.meta, &mut buf);
-        buf.put_u32(meta_offset as u32);
+        let index = write_section(&mut buf, |buf| {
+            BlockMeta::encode_block_meta(&self.meta, buf)
+        });
+        let bloom = Bloom::build_from_key_hashes(&self.key_hashes, self.bloom_bits_per_key);
+        let filter = write_section(&mut buf, |buf| bloom.encode(buf));
+        let mut properties = self.properties;
+        properties.first_key = self.meta[0].first_key.clone();
+        properties.last_key = self.last_key.into();
+        properties.creation_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let meta = write_section(&mut buf, |buf| properties.encode(buf));
+        Footer {
+            version: FORMAT_VERSION,
+            index,
+            filter,
+            meta,
+        }
+        .encode(&mut buf);
         let file = FileObject::create(path.as_ref(), buf)?;
         Ok(SsTable {
             id,
@@ -81,6 +143,9 @@ impl SsTableBuilder {
             block_metas: self.meta,
             block_meta_offset: meta_offset,
             block_cache,
+            properties,
+            bloom,
+            format_version: FORMAT_VERSION,
         })
     }
 
@@ -89,3 +154,14 @@ impl SsTableBuilder {
         self.build(0, None, path)
     }
 }
+
+/// Append a section written by `encode` and its checksum to `buf`.
+fn write_section(buf: &mut Vec<u8>, encode: impl FnOnce(&mut Vec<u8>)) -> SectionHandle {
+    let offset = buf.len();
+    encode(buf);
+    buf.put_u32(crc32(&buf[offset..]));
+    SectionHandle {
+        offset: offset as u64,
+        len: (buf.len() - offset) as u64,
+    }
+}
@@ -0,0 +1,182 @@
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut};
+
+use super::{verify_checksum, FileObject};
+use crate::checksum::crc32;
+
+/// The first SST format, with u16 lengths in blocks and u32 offsets. It is only read, never
+/// written.
+pub(super) const FORMAT_V0: u32 = 0;
+/// Varint lengths in blocks and u64 offsets, so that entries and blocks can be of any size. It is
+/// only read, never written.
+pub(super) const FORMAT_V1: u32 = 1;
+/// A fixed-size footer with a magic number, and the offsets and lengths of all sections. It is
+/// only read, never written.
+pub(super) const FORMAT_V2: u32 = 2;
+/// A sequence number in every entry of the blocks.
+pub(super) const FORMAT_V3: u32 = 3;
+/// The format new SSTs are written in.
+pub(super) const FORMAT_VERSION: u32 = FORMAT_V3;
+
+/// The last 8 bytes of an SST since format 2, "mini-lsm" in ASCII.
+const MAGIC: u64 = 0x6d69_6e69_2d6c_736d;
+
+/// Size of the footer of format 0: the offsets of the block metas and the bloom filter (u32
+/// each), and their checksum.
+const FOOTER_SIZE_V0: u64 = 12;
+/// Size of the footer of format 1: the offsets of the block metas and the bloom filter (u64
+/// each), the format version (u32), and their checksum.
+const FOOTER_SIZE_V1: u64 = 24;
+/// Size of the footer since format 2: the handles of the index, the filter and the meta section
+/// (u64 offset and u64 length each), the format version (u32), the checksum of all of them, and
+/// the magic number (u64).
+const FOOTER_SIZE: u64 = 64;
+
+/// Where a section of an SST is. The length includes the checksum that follows the section. An
+/// empty section is not in the file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct SectionHandle {
+    pub(super) offset: u64,
+    pub(super) len: u64,
+}
+
+impl SectionHandle {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u64(self.offset);
+        buf.put_u64(self.len);
+    }
+
+    fn decode(buf: &mut &[u8]) -> Self {
+        Self {
+            offset: buf.get_u64(),
+            len: buf.get_u64(),
+        }
+    }
+}
+
+/// The end of an SST, which tells the format version and where the other sections are.
+pub(super) struct Footer {
+    pub(super) version: u32,
+    /// The block metas, which index the data blocks.
+    pub(super) index: SectionHandle,
+    /// The bloom filter.
+    pub(super) filter: SectionHandle,
+    /// Metadata about the SST.
+    pub(super) meta: SectionHandle,
+}
+
+impl Footer {
+    /// Encode a footer of the current format to a buffer.
+    pub(super) fn encode(&self, buf: &mut Vec<u8>) {
+        let footer_offset = buf.len();
+        self.index.encode(buf);
+        self.filter.encode(buf);
+        self.meta.encode(buf);
+        buf.put_u32(self.version);
+        buf.put_u32(crc32(&buf[footer_offset..]));
+        buf.put_u64(MAGIC);
+    }
+
+    /// Read the footer of an SST, or return `None` if the file does not end with a valid footer.
+    /// Fails if the SST is of a format newer than this code.
+    pub(super) fn read(file: &FileObject, sst_id: usize) -> Result<Option<Self>> {
+        let len = file.size();
+        if len >= FOOTER_SIZE {
+            let raw_footer = file.read(len - FOOTER_SIZE, FOOTER_SIZE)?;
+            let (raw_footer, mut magic) = raw_footer.split_at(raw_footer.len() - 8);
+            if magic.get_u64() == MAGIC {
+                let mut footer = match verify_checksum(raw_footer) {
+                    Some(footer) => footer,
+                    None => return Ok(None),
+                };
+                let index = SectionHandle::decode(&mut footer);
+                let filter = SectionHandle::decode(&mut footer);
+                let meta = SectionHandle::decode(&mut footer);
+                let version = footer.get_u32();
+                if version > FORMAT_VERSION {
+                    bail!(
+                        "SST {} is of format version {}, but only versions up to {} are supported",
+                        sst_id,
+                        version,
+                        FORMAT_VERSION
+                    );
+                }
+                return Ok(Self {
+                    version,
+                    index,
+                    filter,
+                    meta,
+                }
+                .validate(len - FOOTER_SIZE));
+            }
+        }
+        // Before format 2, the footer had no magic number.
+        if len >= FOOTER_SIZE_V1 {
+            let raw_footer = file.read(len - FOOTER_SIZE_V1, FOOTER_SIZE_V1)?;
+            if let Some(mut footer) = verify_checksum(&raw_footer) {
+                let index_offset = footer.get_u64();
+                let filter_offset = footer.get_u64();
+                if footer.get_u32() == FORMAT_V1 {
+                    return Ok(Self::from_offsets(
+                        FORMAT_V1,
+                        index_offset,
+                        filter_offset,
+                        len - FOOTER_SIZE_V1,
+                    ));
+                }
+            }
+        }
+        if len >= FOOTER_SIZE_V0 {
+            let raw_footer = file.read(len - FOOTER_SIZE_V0, FOOTER_SIZE_V0)?;
+            if let Some(mut footer) = verify_checksum(&raw_footer) {
+                let index_offset = footer.get_u32() as u64;
+                let filter_offset = footer.get_u32() as u64;
+                return Ok(Self::from_offsets(
+                    FORMAT_V0,
+                    index_offset,
+                    filter_offset,
+                    len - FOOTER_SIZE_V0,
+                ));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Build a footer from the offsets in the footers before format 2, where the index is followed
+    /// by the filter, which is followed by the footer.
+    fn from_offsets(
+        version: u32,
+        index_offset: u64,
+        filter_offset: u64,
+        footer_offset: u64,
+    ) -> Option<Self> {
+        if index_offset > filter_offset || filter_offset > footer_offset {
+            return None;
+        }
+        Some(Self {
+            version,
+            index: SectionHandle {
+                offset: index_offset,
+                len: filter_offset - index_offset,
+            },
+            filter: SectionHandle {
+                offset: filter_offset,
+                len: footer_offset - filter_offset,
+            },
+            meta: SectionHandle::default(),
+        })
+    }
+
+    /// Check that all sections are before the footer.
+    fn validate(self, footer_offset: u64) -> Option<Self> {
+        let sections = [self.index, self.filter, self.meta];
+        if sections.iter().any(|s| {
+            s.offset
+                .checked_add(s.len)
+                .map_or(true, |end| end > footer_offset)
+        }) {
+            return None;
+        }
+        Some(self)
+    }
+}
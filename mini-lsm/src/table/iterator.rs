@@ -1,16 +1,22 @@
+use std::ops::Bound;
 use std::sync::Arc;
 
 use anyhow::Result;
+use bytes::Bytes;
 
 use super::SsTable;
 use crate::block::BlockIterator;
 use crate::iterators::StorageIterator;
+use crate::mem_table::map_bound;
+use crate::value::ValueKind;
 
 /// An iterator over the contents of an SSTable.
 pub struct SsTableIterator {
     table: Arc<SsTable>,
     blk_iter: BlockIterator,
     blk_idx: usize,
+    /// The iterator is invalid after the keys past this bound.
+    upper: Bound<Bytes>,
 }
 
 impl SsTableIterator {
@@ -28,6 +34,7 @@ impl SsTableIterator {
             blk_iter,
             table,
             blk_idx,
+            upper: Bound::Unbounded,
         };
         Ok(iter)
     }
@@ -54,24 +61,39 @@ impl SsTableIterator {
         Ok((blk_idx, blk_iter))
     }
 
-    /// Create a new iterator and seek to the first key-value pair which >= `key`.
+    /// Create a new iterator and seek to the newest version of the first key which >= `key`.
     pub fn create_and_seek_to_key(table: Arc<SsTable>, key: &[u8]) -> Result<Self> {
         let (blk_idx, blk_iter) = Self::seek_to_key_inner(&table, key)?;
         let iter = Self {
             blk_iter,
             table,
             blk_idx,
+            upper: Bound::Unbounded,
         };
         Ok(iter)
     }
 
-    /// Seek to the first key-value pair which >= `key`.
+    /// Seek to the newest version of the first key which >= `key`.
     pub fn seek_to_key(&mut self, key: &[u8]) -> Result<()> {
         let (blk_idx, blk_iter) = Self::seek_to_key_inner(&self.table, key)?;
         self.blk_iter = blk_iter;
         self.blk_idx = blk_idx;
         Ok(())
     }
+
+    /// Make the iterator invalid once it moves past `upper`. The blocks after it are not read.
+    pub fn set_upper_bound(&mut self, upper: Bound<&[u8]>) {
+        self.upper = map_bound(upper);
+    }
+
+    /// Check if `key` is within the upper bound.
+    pub(crate) fn within_upper_bound(upper: &Bound<Bytes>, key: &[u8]) -> bool {
+        match upper {
+            Bound::Included(upper) => key <= &upper[..],
+            Bound::Excluded(upper) => key < &upper[..],
+            Bound::Unbounded => true,
+        }
+    }
 }
 
 impl StorageIterator for SsTableIterator {
@@ -79,19 +101,32 @@ impl StorageIterator for SsTableIterator {
         self.blk_iter.value()
     }
 
+    fn kind(&self) -> ValueKind {
+        self.blk_iter.kind()
+    }
+
+    fn seq(&self) -> u64 {
+        self.blk_iter.seq()
+    }
+
     fn key(&self) -> &[u8] {
         self.blk_iter.key()
     }
 
     fn is_valid(&self) -> bool {
-        self.blk_iter.is_valid()
+        self.blk_iter.is_valid() && Self::within_upper_bound(&self.upper, self.blk_iter.key())
     }
 
     fn next(&mut self) -> Result<()> {
         self.blk_iter.next();
         if !self.blk_iter.is_valid() {
             self.blk_idx += 1;
-            if self.blk_idx < self.table.num_of_blocks() {
+            if self.blk_idx < self.table.num_of_blocks()
+                && Self::within_upper_bound(
+                    &self.upper,
+                    &self.table.block_metas[self.blk_idx].first_key,
+                )
+            {
                 self.blk_iter = BlockIterator::create_and_seek_to_first(
                     self.table.read_block_cached(self.blk_idx)?,
                 );
@@ -0,0 +1,89 @@
+use anyhow::{ensure, Result};
+use bytes::{Buf, Bytes};
+
+use crate::varint::{get_varint, put_varint};
+
+/// Statistics of an SST, written when it is built.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TableProperties {
+    /// The first key of the SST.
+    pub first_key: Bytes,
+    /// The last key of the SST.
+    pub last_key: Bytes,
+    /// Number of entries, including deletes.
+    pub num_entries: u64,
+    /// Number of deletes.
+    pub num_deletions: u64,
+    /// Total size of the keys in bytes, before prefix compression.
+    pub raw_key_size: u64,
+    /// Total size of the values in bytes.
+    pub raw_value_size: u64,
+    /// When the SST was built, in seconds since the Unix epoch.
+    pub creation_time: u64,
+    /// The largest sequence number of the entries.
+    pub max_seq: u64,
+}
+
+impl TableProperties {
+    const FIRST_KEY: u64 = 0;
+    const LAST_KEY: u64 = 1;
+    const NUM_ENTRIES: u64 = 2;
+    const NUM_DELETIONS: u64 = 3;
+    const RAW_KEY_SIZE: u64 = 4;
+    const RAW_VALUE_SIZE: u64 = 5;
+    const CREATION_TIME: u64 = 6;
+    const MAX_SEQ: u64 = 7;
+
+    /// Encode the properties to a buffer.
+    ///
+    /// Each property is encoded as `id (varint) | len (varint) | value`, where integers are
+    /// varints. Unknown ids are skipped when decoding, so that properties can be added without a
+    /// new format version.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let put_bytes = |buf: &mut Vec<u8>, id: u64, value: &[u8]| {
+            put_varint(buf, id);
+            put_varint(buf, value.len() as u64);
+            buf.extend_from_slice(value);
+        };
+        let put_u64 = |buf: &mut Vec<u8>, id: u64, value: u64| {
+            let mut encoded = Vec::new();
+            put_varint(&mut encoded, value);
+            put_bytes(buf, id, &encoded);
+        };
+        put_bytes(buf, Self::FIRST_KEY, &self.first_key);
+        put_bytes(buf, Self::LAST_KEY, &self.last_key);
+        put_u64(buf, Self::NUM_ENTRIES, self.num_entries);
+        put_u64(buf, Self::NUM_DELETIONS, self.num_deletions);
+        put_u64(buf, Self::RAW_KEY_SIZE, self.raw_key_size);
+        put_u64(buf, Self::RAW_VALUE_SIZE, self.raw_value_size);
+        put_u64(buf, Self::CREATION_TIME, self.creation_time);
+        put_u64(buf, Self::MAX_SEQ, self.max_seq);
+    }
+
+    /// Decode the properties from a buffer.
+    pub fn decode(mut buf: &[u8]) -> Result<Self> {
+        let mut properties = Self::default();
+        while buf.has_remaining() {
+            let id = get_varint(&mut buf);
+            let len = get_varint(&mut buf) as usize;
+            ensure!(buf.remaining() >= len, "property {} is truncated", id);
+            let mut value = &buf[..len];
+            match id {
+                Self::FIRST_KEY => properties.first_key = Bytes::copy_from_slice(value),
+                Self::LAST_KEY => properties.last_key = Bytes::copy_from_slice(value),
+                Self::NUM_ENTRIES => properties.num_entries = get_varint(&mut value),
+                Self::NUM_DELETIONS => properties.num_deletions = get_varint(&mut value),
+                Self::RAW_KEY_SIZE => properties.raw_key_size = get_varint(&mut value),
+                Self::RAW_VALUE_SIZE => properties.raw_value_size = get_varint(&mut value),
+                Self::CREATION_TIME => properties.creation_time = get_varint(&mut value),
+                Self::MAX_SEQ => properties.max_seq = get_varint(&mut value),
+                _ => {}
+            }
+            buf.advance(len);
+        }
+        Ok(properties)
+    }
+}
+
+#[cfg(test)]
+mod tests;
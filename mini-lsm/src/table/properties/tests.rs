@@ -0,0 +1,21 @@
+use bytes::{BufMut, Bytes};
+
+use super::TableProperties;
+use crate::varint::put_varint;
+
+#[test]
+fn test_table_properties_skip_unknown() {
+    let properties = TableProperties {
+        first_key: Bytes::from("a"),
+        last_key: Bytes::from("b"),
+        num_entries: 2,
+        ..Default::default()
+    };
+    let mut buf = Vec::new();
+    // A property added by a later version.
+    put_varint(&mut buf, 1000);
+    put_varint(&mut buf, 3);
+    buf.put_slice(b"new");
+    properties.encode(&mut buf);
+    assert_eq!(TableProperties::decode(&buf).unwrap(), properties);
+}
@@ -5,7 +5,9 @@ use tempfile::{tempdir, TempDir};
 
 use super::*;
 use crate::iterators::StorageIterator;
+use crate::key::MAX_SEQ;
 use crate::table::SsTableBuilder;
+use crate::value::ValueKind;
 
 #[test]
 fn test_sst_build_single_key() {
@@ -128,3 +130,452 @@ fn test_sst_seek_key() {
         iter.seek_to_key(b"k").unwrap();
     }
 }
+
+#[test]
+fn test_sst_upper_bound() {
+    use std::ops::Bound;
+    let (dir, sst) = generate_sst();
+    let path = dir.path().join("1.sst");
+    let block_idx = sst.num_of_blocks() / 2;
+    let offset = sst.block_metas[block_idx].offset;
+    let bound = sst.block_metas[block_idx].first_key.clone();
+    drop(sst);
+    // The block past the upper bound is corrupted, so reading it would fail.
+    corrupt_sst(&path, offset as i64 + 1);
+    let sst = Arc::new(SsTable::open(1, None, FileObject::open(&path).unwrap()).unwrap());
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    iter.set_upper_bound(Bound::Excluded(&bound));
+    let mut count = 0;
+    while iter.is_valid() {
+        assert_eq!(iter.key(), key_of(count));
+        assert!(iter.key() < &bound[..]);
+        iter.next().unwrap();
+        count += 1;
+    }
+    assert!(count > 0);
+
+    let mut iter = SsTableIterator::create_and_seek_to_key(sst, &key_of(1)).unwrap();
+    iter.set_upper_bound(Bound::Included(&key_of(3)));
+    for i in 1..=3 {
+        assert_eq!(iter.key(), key_of(i));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_bloom_filter() {
+    let (dir, sst) = generate_sst();
+    let path = dir.path().join("1.sst");
+    drop(sst);
+    let sst = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    for i in 0..num_of_keys() {
+        assert!(sst.may_contain(&key_of(i)));
+    }
+    let false_positives = (0..num_of_keys())
+        .filter(|i| sst.may_contain(format!("key_{:03}", i * 5 + 1).as_bytes()))
+        .count();
+    assert!(false_positives < num_of_keys() / 10);
+}
+
+/// Flip a bit of the SST at `path`, `offset` bytes from the start, or from the end if negative.
+fn corrupt_sst(path: &std::path::Path, offset: i64) {
+    let mut data = std::fs::read(path).unwrap();
+    let idx = if offset < 0 {
+        (data.len() as i64 + offset) as usize
+    } else {
+        offset as usize
+    };
+    data[idx] ^= 1;
+    std::fs::write(path, data).unwrap();
+}
+
+#[test]
+fn test_sst_corrupted_block() {
+    let (dir, sst) = generate_sst();
+    let path = dir.path().join("1.sst");
+    let block_idx = sst.num_of_blocks() / 2;
+    let offset = sst.block_metas[block_idx].offset;
+    drop(sst);
+    corrupt_sst(&path, offset as i64 + 1);
+    let sst = SsTable::open(1, None, FileObject::open(&path).unwrap()).unwrap();
+    assert!(sst.read_block(block_idx - 1).is_ok());
+    let err = sst.read_block(block_idx).err().unwrap();
+    assert_eq!(
+        err.downcast_ref::<CorruptionError>(),
+        Some(&CorruptionError {
+            sst_id: 1,
+            block_idx: Some(block_idx),
+        })
+    );
+    // The error should keep its type through the block cache.
+    let sst = SsTable::open(1, Some(Arc::new(BlockCache::new(16))), sst.file).unwrap();
+    let err = sst.read_block_cached(block_idx).err().unwrap();
+    assert!(err.downcast_ref::<CorruptionError>().is_some());
+}
+
+#[test]
+fn test_sst_corrupted_meta() {
+    // Flip a bit in the footer, the bloom filter, and the block metas.
+    for region in 0..3 {
+        let (dir, sst) = generate_sst();
+        let path = dir.path().join("1.sst");
+        let offset = match region {
+            0 => -2,
+            1 => -70,
+            _ => sst.block_meta_offset as i64 + 1,
+        };
+        drop(sst);
+        corrupt_sst(&path, offset);
+        let err = SsTable::open(1, None, FileObject::open(&path).unwrap())
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.downcast_ref::<CorruptionError>(),
+            Some(&CorruptionError {
+                sst_id: 1,
+                block_idx: None,
+            })
+        );
+    }
+}
+
+#[test]
+fn test_sst_truncated() {
+    let (dir, sst) = generate_sst();
+    let path = dir.path().join("1.sst");
+    let len = sst.table_size();
+    drop(sst);
+    let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(len - 1).unwrap();
+    assert!(SsTable::open(1, None, FileObject::open(&path).unwrap()).is_err());
+    file.set_len(3).unwrap();
+    assert!(SsTable::open(1, None, FileObject::open(&path).unwrap()).is_err());
+}
+
+#[test]
+fn test_sst_value_kind() {
+    let mut builder = SsTableBuilder::new(32);
+    builder.add(b"key1", b"");
+    builder.add_entry(b"key2", 0, ValueKind::Delete, b"");
+    builder.add(b"key3", b"value3");
+    let dir = tempdir().unwrap();
+    let sst = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+    assert!(sst.num_of_blocks() >= 2);
+    assert_eq!(
+        sst.get(b"key1", MAX_SEQ).unwrap(),
+        Some((ValueKind::Put, Bytes::new()))
+    );
+    assert_eq!(
+        sst.get(b"key2", MAX_SEQ).unwrap(),
+        Some((ValueKind::Delete, Bytes::new()))
+    );
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    for (key, kind) in [
+        (b"key1", ValueKind::Put),
+        (b"key2", ValueKind::Delete),
+        (b"key3", ValueKind::Put),
+    ] {
+        assert_eq!(iter.key(), key);
+        assert_eq!(iter.kind(), kind);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_oversized_entries() {
+    let large_value = vec![b'v'; 100000];
+    let mut builder = SsTableBuilder::new(4096);
+    builder.add(b"key1", b"value1");
+    builder.add(b"key2", &large_value);
+    builder.add(b"key3", b"value3");
+    builder.add(b"key4", &large_value);
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    builder.build_for_test(&path).unwrap();
+    let sst = Arc::new(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap());
+    // Each large value is in its own block.
+    assert_eq!(sst.num_of_blocks(), 4);
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    for (key, value) in [
+        (b"key1", &b"value1"[..]),
+        (b"key2", &large_value),
+        (b"key3", b"value3"),
+        (b"key4", &large_value),
+    ] {
+        assert_eq!(iter.key(), key);
+        assert_eq!(iter.value(), value);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+/// Write an SST in the first format, with one entry per block.
+fn build_sst_v0(path: &std::path::Path) {
+    use crate::checksum::crc32;
+
+    let mut buf = Vec::new();
+    let mut metas = Vec::new();
+    for idx in 0..num_of_keys() {
+        let (key, value) = (key_of(idx), value_of(idx));
+        let offset = buf.len();
+        buf.put_u16(0);
+        buf.put_u16(key.len() as u16);
+        buf.put_slice(&key);
+        buf.put_u8(ValueKind::Put.encode());
+        buf.put_u16(value.len() as u16);
+        buf.put_slice(&value);
+        buf.put_u16(0);
+        buf.put_u16(1);
+        buf.put_u32(crc32(&buf[offset..]));
+        metas.push((offset, key));
+    }
+    let meta_offset = buf.len();
+    for (offset, key) in metas {
+        buf.put_u32(offset as u32);
+        buf.put_u16(key.len() as u16);
+        buf.put_slice(&key);
+    }
+    buf.put_u32(crc32(&buf[meta_offset..]));
+    let bloom_offset = buf.len();
+    let hashes: Vec<u32> = (0..num_of_keys())
+        .map(|idx| Bloom::hash(&key_of(idx)))
+        .collect();
+    Bloom::build_from_key_hashes(&hashes, 10).encode(&mut buf);
+    buf.put_u32(crc32(&buf[bloom_offset..]));
+    let footer_offset = buf.len();
+    buf.put_u32(meta_offset as u32);
+    buf.put_u32(bloom_offset as u32);
+    buf.put_u32(crc32(&buf[footer_offset..]));
+    std::fs::write(path, buf).unwrap();
+}
+
+#[test]
+fn test_sst_read_v0() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    build_sst_v0(&path);
+    let sst = Arc::new(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap());
+    assert_eq!(sst.num_of_blocks(), num_of_keys());
+    assert_eq!(sst.last_key(), &key_of(num_of_keys() - 1)[..]);
+    assert!(sst.may_contain(&key_of(7)));
+    assert_eq!(
+        sst.get(&key_of(7), MAX_SEQ).unwrap(),
+        Some((ValueKind::Put, Bytes::from(value_of(7))))
+    );
+    let mut iter = SsTableIterator::create_and_seek_to_key(sst, &key_of(10)).unwrap();
+    for idx in 10..num_of_keys() {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_not_an_sst() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    std::fs::write(&path, vec![b'x'; 1000]).unwrap();
+    let err = SsTable::open(1, None, FileObject::open(&path).unwrap())
+        .err()
+        .unwrap();
+    assert!(err.downcast_ref::<CorruptionError>().is_some());
+    std::fs::write(&path, b"").unwrap();
+    assert!(SsTable::open(1, None, FileObject::open(&path).unwrap()).is_err());
+}
+
+#[test]
+fn test_sst_unsupported_version() {
+    let (dir, sst) = generate_sst();
+    let path = dir.path().join("1.sst");
+    let mut data = std::fs::read(&path).unwrap();
+    drop(sst);
+    let len = data.len();
+    // Bump the version and fix the checksum, as a newer version would write it.
+    (&mut data[len - 16..len - 12]).put_u32(footer::FORMAT_VERSION + 1);
+    let checksum = crate::checksum::crc32(&data[len - 64..len - 12]);
+    (&mut data[len - 12..len - 8]).put_u32(checksum);
+    std::fs::write(&path, data).unwrap();
+    let err = SsTable::open(1, None, FileObject::open(&path).unwrap())
+        .err()
+        .unwrap();
+    assert!(err.downcast_ref::<CorruptionError>().is_none());
+    assert!(err.to_string().contains("format version"), "{}", err);
+}
+
+/// Write an SST in format 1 or 2, with one entry per block and no sequence numbers.
+fn build_sst_v1(path: &std::path::Path, version: u32) {
+    use crate::checksum::crc32;
+    use crate::varint::put_varint;
+
+    let mut buf = Vec::new();
+    let mut metas = Vec::new();
+    for idx in 0..num_of_keys() {
+        let (key, value) = (key_of(idx), value_of(idx));
+        let offset = buf.len();
+        put_varint(&mut buf, 0);
+        put_varint(&mut buf, key.len() as u64);
+        buf.put_slice(&key);
+        buf.put_u8(ValueKind::Put.encode());
+        put_varint(&mut buf, value.len() as u64);
+        buf.put_slice(&value);
+        buf.put_u32(0);
+        buf.put_u32(1);
+        buf.put_u32(crc32(&buf[offset..]));
+        metas.push(BlockMeta {
+            offset,
+            first_key: key.into(),
+        });
+    }
+    let index_offset = buf.len() as u64;
+    BlockMeta::encode_block_meta(&metas, &mut buf);
+    buf.put_u32(crc32(&buf[index_offset as usize..]));
+    let filter_offset = buf.len() as u64;
+    let hashes: Vec<u32> = (0..num_of_keys())
+        .map(|idx| Bloom::hash(&key_of(idx)))
+        .collect();
+    Bloom::build_from_key_hashes(&hashes, 10).encode(&mut buf);
+    buf.put_u32(crc32(&buf[filter_offset as usize..]));
+    let footer_offset = buf.len() as u64;
+    if version == footer::FORMAT_V1 {
+        buf.put_u64(index_offset);
+        buf.put_u64(filter_offset);
+        buf.put_u32(footer::FORMAT_V1);
+        buf.put_u32(crc32(&buf[footer_offset as usize..]));
+    } else {
+        footer::Footer {
+            version,
+            index: footer::SectionHandle {
+                offset: index_offset,
+                len: filter_offset - index_offset,
+            },
+            filter: footer::SectionHandle {
+                offset: filter_offset,
+                len: footer_offset - filter_offset,
+            },
+            meta: footer::SectionHandle::default(),
+        }
+        .encode(&mut buf);
+    }
+    std::fs::write(path, buf).unwrap();
+}
+
+#[test]
+fn test_sst_read_v1_and_v2() {
+    for version in [footer::FORMAT_V1, footer::FORMAT_V2] {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("1.sst");
+        build_sst_v1(&path, version);
+        let sst = Arc::new(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap());
+        assert_eq!(sst.format_version, version);
+        assert_eq!(sst.properties().max_seq, 0);
+        assert_eq!(
+            sst.get(&key_of(7), 0).unwrap(),
+            Some((ValueKind::Put, Bytes::from(value_of(7))))
+        );
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+        for idx in 0..num_of_keys() {
+            assert_eq!(iter.key(), key_of(idx));
+            assert_eq!(iter.seq(), 0);
+            assert_eq!(iter.value(), value_of(idx));
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    }
+}
+
+#[test]
+fn test_sst_versions() {
+    // Many versions of a key, so that they span several blocks.
+    let mut builder = SsTableBuilder::new(64);
+    builder.add_entry(b"key1", 1, ValueKind::Put, b"value1");
+    for seq in (2..=20).rev() {
+        builder.add_entry(
+            b"key2",
+            seq,
+            ValueKind::Put,
+            format!("value{}", seq).as_bytes(),
+        );
+    }
+    builder.add_entry(b"key3", 21, ValueKind::Delete, b"");
+    builder.add_entry(b"key3", 3, ValueKind::Put, b"value3");
+    let dir = tempdir().unwrap();
+    let sst = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+    assert!(sst.num_of_blocks() > 3);
+    assert_eq!(sst.properties().max_seq, 21);
+    assert_eq!(sst.get(b"key1", 0).unwrap(), None);
+    assert_eq!(
+        sst.get(b"key1", MAX_SEQ).unwrap(),
+        Some((ValueKind::Put, Bytes::from("value1")))
+    );
+    for seq in 2..=20 {
+        assert_eq!(
+            sst.get(b"key2", seq).unwrap(),
+            Some((ValueKind::Put, Bytes::from(format!("value{}", seq))))
+        );
+    }
+    assert_eq!(sst.get(b"key2", 1).unwrap(), None);
+    assert_eq!(
+        sst.get(b"key3", MAX_SEQ).unwrap(),
+        Some((ValueKind::Delete, Bytes::new()))
+    );
+    assert_eq!(
+        sst.get(b"key3", 20).unwrap(),
+        Some((ValueKind::Put, Bytes::from("value3")))
+    );
+
+    // Seeking finds the newest version, even if older versions start the following blocks.
+    let mut iter = SsTableIterator::create_and_seek_to_key(sst, b"key2").unwrap();
+    for seq in (2..=20).rev() {
+        assert_eq!(iter.key(), b"key2");
+        assert_eq!(iter.seq(), seq);
+        iter.next().unwrap();
+    }
+    assert_eq!(iter.key(), b"key3");
+    assert_eq!(iter.seq(), 21);
+}
+
+#[test]
+fn test_sst_properties() {
+    let mut builder = SsTableBuilder::new(64);
+    builder.add(b"key1", b"value1");
+    builder.add_entry(b"key2", 5, ValueKind::Delete, b"");
+    builder.add(b"key3", b"");
+    builder.add(b"key4", b"value4");
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let sst = builder.build_for_test(&path).unwrap();
+    let expected = TableProperties {
+        first_key: Bytes::from("key1"),
+        last_key: Bytes::from("key4"),
+        num_entries: 4,
+        num_deletions: 1,
+        raw_key_size: 16,
+        raw_value_size: 12,
+        creation_time: sst.properties().creation_time,
+        max_seq: 5,
+    };
+    assert_eq!(sst.properties(), &expected);
+    assert!(expected.creation_time > 0);
+    drop(sst);
+    let sst = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(sst.properties(), &expected);
+}
+
+#[test]
+fn test_sst_properties_of_old_format() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    build_sst_v0(&path);
+    let sst = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    let properties = sst.properties();
+    assert_eq!(properties.first_key, key_of(0));
+    assert_eq!(properties.last_key, key_of(num_of_keys() - 1));
+    assert_eq!(properties.num_entries, num_of_keys() as u64);
+    assert_eq!(properties.num_deletions, 0);
+    assert_eq!(properties.raw_key_size, (num_of_keys() * 7) as u64);
+    assert_eq!(properties.raw_value_size, (num_of_keys() * 16) as u64);
+    assert!(properties.creation_time > 0);
+}
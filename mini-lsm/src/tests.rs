@@ -1 +1,9 @@
+pub mod day10_tests;
+pub mod day11_tests;
 pub mod day4_tests;
+pub mod day5_tests;
+pub mod day6_tests;
+pub mod day7_tests;
+pub mod day8_tests;
+pub mod day9_tests;
+pub mod harness;
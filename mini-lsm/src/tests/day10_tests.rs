@@ -0,0 +1,354 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+use super::harness::{check_storage, key_of, leveled_compaction, options_with_compaction};
+use crate::compact::{
+    CompactionDecision, CompactionFilter, CompactionOptions, FifoCompactionOptions,
+    TieredCompactionOptions, TtlCompactionFilter,
+};
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::table::SsTable;
+
+fn tiered_options() -> LsmStorageOptions {
+    options_with_compaction(CompactionOptions::Tiered(TieredCompactionOptions {
+        level0_file_num_compaction_trigger: 2,
+        num_tiers: 3,
+        size_ratio: 1,
+        max_size_amplification_percent: 200,
+    }))
+}
+
+#[test]
+fn test_storage_tiered_compaction() {
+    let dir = tempdir().unwrap();
+    let mut expected = BTreeMap::new();
+    let mut max_tiers = 0;
+    {
+        let storage = LsmStorage::open_with_options(&dir, tiered_options()).unwrap();
+        for round in 0..20 {
+            for i in 0..50 {
+                let idx = (i * 7 + round * 13) % 500;
+                let value = format!("value_{}_{}", idx, round).into_bytes();
+                storage.put(&key_of(idx), &value).unwrap();
+                expected.insert(key_of(idx), value);
+            }
+            for i in 0..5 {
+                let idx = (i * 31 + round * 17) % 500;
+                storage.delete(&key_of(idx)).unwrap();
+                expected.remove(&key_of(idx));
+            }
+            storage.sync().unwrap();
+            storage.compact().unwrap();
+
+            let snapshot = storage.core().inner.read().clone();
+            assert!(snapshot.l0_sstables.len() < 2);
+            assert_eq!(snapshot.levels.len(), 3);
+            let tiers = snapshot.levels.iter().filter(|level| !level.is_empty());
+            max_tiers = max_tiers.max(tiers.count());
+            for level in &snapshot.levels {
+                for window in level.windows(2) {
+                    assert!(window[0].last_key() < window[1].first_key());
+                }
+            }
+            check_storage(&storage, &expected);
+        }
+    }
+    // Tiers of different sizes are kept apart.
+    assert!(max_tiers > 1);
+
+    // The tiers are restored from the manifest.
+    let storage = LsmStorage::open_with_options(&dir, tiered_options()).unwrap();
+    check_storage(&storage, &expected);
+}
+
+#[test]
+fn test_storage_tiered_compaction_space_amplification() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(
+        &dir,
+        LsmStorageOptions {
+            compaction_options: CompactionOptions::Tiered(TieredCompactionOptions {
+                level0_file_num_compaction_trigger: 1,
+                num_tiers: 3,
+                size_ratio: 1,
+                max_size_amplification_percent: 50,
+            }),
+            ..tiered_options()
+        },
+    )
+    .unwrap();
+    // Overwriting the same keys makes the newer data about as large as the oldest tier, so
+    // everything is merged into one tier, where the overwritten versions are dropped.
+    for round in 0..5 {
+        for i in 0..100 {
+            storage
+                .put(&key_of(i), format!("value_{}", round).as_bytes())
+                .unwrap();
+        }
+        storage.sync().unwrap();
+        storage.compact().unwrap();
+        let snapshot = storage.core().inner.read().clone();
+        assert!(snapshot.l0_sstables.is_empty());
+        let tiers: Vec<_> = snapshot
+            .levels
+            .iter()
+            .filter(|level| !level.is_empty())
+            .collect();
+        assert_eq!(tiers.len(), 1);
+        let num_entries: u64 = tiers[0]
+            .iter()
+            .map(|table| table.properties().num_entries)
+            .sum();
+        assert_eq!(num_entries, 100);
+    }
+}
+
+fn fifo_options(max_table_files_size: u64, ttl: Option<Duration>) -> LsmStorageOptions {
+    options_with_compaction(CompactionOptions::Fifo(FifoCompactionOptions {
+        max_table_files_size,
+        ttl,
+    }))
+}
+
+#[test]
+fn test_storage_fifo_compaction_size_limit() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, fifo_options(u64::MAX, None)).unwrap();
+    for sst in 0..10 {
+        for i in 0..100 {
+            storage.put(&key_of(sst * 100 + i), b"value").unwrap();
+        }
+        storage.sync().unwrap();
+    }
+    let ssts = storage.core().inner.read().l0_sstables.clone();
+    assert_eq!(ssts.len(), 10);
+    let size_limit = ssts[7..].iter().map(|table| table.table_size()).sum();
+    drop(storage);
+
+    // Only the 3 newest SSTs fit, and they are kept as they are.
+    let storage = LsmStorage::open_with_options(&dir, fifo_options(size_limit, None)).unwrap();
+    storage.compact().unwrap();
+    let snapshot = storage.core().inner.read().clone();
+    let ids: Vec<usize> = snapshot
+        .l0_sstables
+        .iter()
+        .map(|table| table.id())
+        .collect();
+    let expected_ids: Vec<usize> = ssts[7..].iter().map(|table| table.id()).collect();
+    assert_eq!(ids, expected_ids);
+    assert!(snapshot.levels.iter().all(|level| level.is_empty()));
+    assert!(storage.get(&key_of(699)).unwrap().is_none());
+    for idx in 700..1000 {
+        assert_eq!(&storage.get(&key_of(idx)).unwrap().unwrap()[..], b"value");
+    }
+    let sst_files = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".sst"))
+        .count();
+    assert_eq!(sst_files, 3);
+}
+
+#[test]
+fn test_storage_fifo_compaction_ttl() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open_with_options(
+            &dir,
+            fifo_options(u64::MAX, Some(Duration::from_secs(3600))),
+        )
+        .unwrap();
+        storage.put(b"a", b"1").unwrap();
+        storage.sync().unwrap();
+        storage.compact().unwrap();
+        assert_eq!(&storage.get(b"a").unwrap().unwrap()[..], b"1");
+        // FIFO compaction does not stall writes however many SSTs pile up in L0.
+        for _ in 0..40 {
+            storage.put(b"b", b"1").unwrap();
+            storage.sync().unwrap();
+        }
+        assert!(matches!(
+            storage.write_stall_condition(),
+            crate::write_stall::WriteStallCondition::Normal
+        ));
+    }
+    let storage =
+        LsmStorage::open_with_options(&dir, fifo_options(u64::MAX, Some(Duration::ZERO))).unwrap();
+    storage.compact().unwrap();
+    assert!(storage.core().inner.read().l0_sstables.is_empty());
+    assert!(storage.get(b"a").unwrap().is_none());
+}
+
+#[test]
+fn test_storage_compact_range() {
+    for compaction_options in [leveled_compaction(), tiered_options().compaction_options] {
+        let dir = tempdir().unwrap();
+        let options = LsmStorageOptions {
+            compaction_options,
+            ..tiered_options()
+        };
+        let mut expected = BTreeMap::new();
+        {
+            let storage = LsmStorage::open_with_options(&dir, options.clone()).unwrap();
+            for round in 0..3 {
+                for idx in 0..300 {
+                    let value = format!("value_{}_{}", idx, round).into_bytes();
+                    storage.put(&key_of(idx), &value).unwrap();
+                    expected.insert(key_of(idx), value);
+                }
+                storage.sync().unwrap();
+            }
+            // A snapshot still sees the deleted keys after the compaction.
+            let snapshot = storage.snapshot();
+            for idx in 100..200 {
+                storage.delete(&key_of(idx)).unwrap();
+                expected.remove(&key_of(idx));
+            }
+            storage
+                .compact_range(Bound::Included(&key_of(100)), Bound::Included(&key_of(199)))
+                .unwrap();
+            assert_eq!(
+                &snapshot.get(&key_of(150)).unwrap().unwrap()[..],
+                b"value_150_2"
+            );
+            drop(snapshot);
+            storage
+                .compact_range(Bound::Included(&key_of(100)), Bound::Included(&key_of(199)))
+                .unwrap();
+
+            let snapshot = storage.core().inner.read().clone();
+            assert!(snapshot.memtable.is_empty());
+            let in_range = |table: &&Arc<SsTable>| {
+                table.first_key()[..] <= key_of(199)[..] && table.last_key()[..] >= key_of(100)[..]
+            };
+            assert_eq!(snapshot.l0_sstables.iter().filter(in_range).count(), 0);
+            let (last_level, upper_levels) = snapshot.levels.split_last().unwrap();
+            assert!(upper_levels.iter().flatten().filter(in_range).count() == 0);
+            for window in last_level.windows(2) {
+                assert!(window[0].last_key() < window[1].first_key());
+            }
+            // The deletes are dropped together with the keys they deleted.
+            for table in last_level.iter().filter(in_range) {
+                assert_eq!(table.properties().num_deletions, 0);
+            }
+            let num_entries: u64 = last_level
+                .iter()
+                .filter(in_range)
+                .map(|table| table.properties().num_entries)
+                .sum();
+            assert!(num_entries <= 200, "{}", num_entries);
+            check_storage(&storage, &expected);
+        }
+        let storage = LsmStorage::open_with_options(&dir, options).unwrap();
+        check_storage(&storage, &expected);
+    }
+}
+
+#[test]
+fn test_storage_compact_range_fifo() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, fifo_options(u64::MAX, None)).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    assert!(storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded)
+        .is_err());
+}
+
+#[test]
+fn test_storage_compaction_filter_ttl() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        compaction_filter: Some(Arc::new(TtlCompactionFilter)),
+        ..tiered_options()
+    };
+    let storage = LsmStorage::open_with_options(&dir, options).unwrap();
+    for idx in 0..100 {
+        // Every other key has already expired.
+        let expire_at = if idx % 2 == 0 { 1 } else { u64::MAX };
+        let value = TtlCompactionFilter::encode_value(b"value", expire_at);
+        storage.put(&key_of(idx), &value).unwrap();
+    }
+    storage.sync().unwrap();
+    // Expired keys are still readable until they are compacted.
+    assert!(storage.get(&key_of(0)).unwrap().is_some());
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    for idx in 0..100 {
+        let value = storage.get(&key_of(idx)).unwrap();
+        if idx % 2 == 0 {
+            assert!(value.is_none());
+        } else {
+            let value = value.unwrap();
+            let (value, expire_at) = TtlCompactionFilter::decode_value(&value).unwrap();
+            assert_eq!(value, b"value");
+            assert_eq!(expire_at, u64::MAX);
+        }
+    }
+    let snapshot = storage.core().inner.read().clone();
+    let tables = || snapshot.levels.iter().flatten();
+    assert!(tables().all(|table| table.properties().num_deletions == 0));
+    let num_entries: u64 = tables().map(|table| table.properties().num_entries).sum();
+    assert_eq!(num_entries, 50);
+}
+
+#[derive(Debug)]
+struct UppercaseFilter;
+
+impl CompactionFilter for UppercaseFilter {
+    fn filter(&self, key: &[u8], value: &[u8]) -> CompactionDecision {
+        if key.ends_with(b"0") {
+            CompactionDecision::Remove
+        } else {
+            CompactionDecision::ChangeValue(value.to_ascii_uppercase().into())
+        }
+    }
+}
+
+#[test]
+fn test_storage_compaction_filter_change_value() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        compaction_filter: Some(Arc::new(UppercaseFilter)),
+        ..tiered_options()
+    };
+    let storage = LsmStorage::open_with_options(&dir, options).unwrap();
+    for idx in 0..20 {
+        storage.put(&key_of(idx), b"old").unwrap();
+    }
+    storage.sync().unwrap();
+    let snapshot = storage.snapshot();
+    for idx in 0..10 {
+        storage.put(&key_of(idx), b"new").unwrap();
+    }
+    storage.sync().unwrap();
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    for idx in 0..20 {
+        // Only the versions no snapshot sees are filtered.
+        let expected: Option<&[u8]> = match idx {
+            0 => None,
+            1..=9 => Some(b"NEW"),
+            _ => Some(b"old"),
+        };
+        assert_eq!(storage.get(&key_of(idx)).unwrap().as_deref(), expected);
+        let value = snapshot.get(&key_of(idx)).unwrap();
+        assert_eq!(value.as_deref(), Some(&b"old"[..]));
+    }
+    drop(snapshot);
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    for idx in 10..20 {
+        let expected: Option<&[u8]> = match idx {
+            10 => None,
+            _ => Some(b"OLD"),
+        };
+        assert_eq!(storage.get(&key_of(idx)).unwrap().as_deref(), expected);
+    }
+}
@@ -0,0 +1,232 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use super::harness::{collect, leveled_compaction};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::merge_operator::{AppendOperator, U64AddOperator};
+use crate::table::SsTableIterator;
+use crate::value::ValueKind;
+use crate::write_batch::WriteBatch;
+
+fn append_options() -> LsmStorageOptions {
+    LsmStorageOptions {
+        merge_operator: Some(Arc::new(AppendOperator)),
+        compaction_options: leveled_compaction(),
+        ..Default::default()
+    }
+}
+
+/// Count the merge operands in all SSTs of the storage.
+fn num_merge_operands(storage: &LsmStorage) -> usize {
+    let snapshot = storage.core().inner.read().clone();
+    let mut count = 0;
+    for table in snapshot
+        .l0_sstables
+        .iter()
+        .chain(snapshot.levels.iter().flatten())
+    {
+        let mut iter = SsTableIterator::create_and_seek_to_first(table.clone()).unwrap();
+        while iter.is_valid() {
+            if iter.kind() == ValueKind::Merge {
+                count += 1;
+            }
+            iter.next().unwrap();
+        }
+    }
+    count
+}
+
+#[test]
+fn test_merge_u64_add() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        merge_operator: Some(Arc::new(U64AddOperator)),
+        ..Default::default()
+    };
+    let get = |storage: &LsmStorage, key: &[u8]| {
+        let value = storage.get(key).unwrap()?;
+        Some(u64::from_be_bytes(value[..].try_into().unwrap()))
+    };
+    {
+        let storage = LsmStorage::open_with_options(&dir, options.clone()).unwrap();
+        storage.put(b"a", &10u64.to_be_bytes()).unwrap();
+        storage.merge(b"a", &1u64.to_be_bytes()).unwrap();
+        storage.merge(b"b", &2u64.to_be_bytes()).unwrap();
+        storage.sync().unwrap();
+        // The operands are combined with the versions in the SSTs.
+        storage.merge(b"a", &5u64.to_be_bytes()).unwrap();
+        storage.merge(b"b", &3u64.to_be_bytes()).unwrap();
+        storage.put(b"c", &7u64.to_be_bytes()).unwrap();
+        storage.delete(b"c").unwrap();
+        storage.merge(b"c", &4u64.to_be_bytes()).unwrap();
+        assert_eq!(get(&storage, b"a"), Some(16));
+        assert_eq!(get(&storage, b"b"), Some(5));
+        // A delete resets the counter.
+        assert_eq!(get(&storage, b"c"), Some(4));
+        assert_eq!(get(&storage, b"d"), None);
+    }
+    // The operands are recovered from the WAL.
+    let storage = LsmStorage::open_with_options(&dir, options).unwrap();
+    assert_eq!(get(&storage, b"a"), Some(16));
+    assert_eq!(get(&storage, b"b"), Some(5));
+    assert_eq!(get(&storage, b"c"), Some(4));
+
+    // An operand that is not a u64 fails the read.
+    storage.merge(b"e", b"bad").unwrap();
+    assert!(storage.get(b"e").is_err());
+
+    // Compaction keeps the bad operand instead of failing, so that only the reads of its key fail.
+    storage.put(b"f", &1u64.to_be_bytes()).unwrap();
+    storage.merge(b"f", b"bad").unwrap();
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    assert!(storage.get(b"e").is_err());
+    assert!(storage.get(b"f").is_err());
+    assert_eq!(get(&storage, b"a"), Some(16));
+    storage.put(b"g", &1u64.to_be_bytes()).unwrap();
+    storage.sync().unwrap();
+    storage.compact().unwrap();
+    assert_eq!(get(&storage, b"g"), Some(1));
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_merge_in_batch() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        merge_operator: Some(Arc::new(U64AddOperator)),
+        ..Default::default()
+    };
+    let check = |storage: &LsmStorage| {
+        let get = |key: &[u8]| {
+            let value = storage.get(key).unwrap()?;
+            Some(u64::from_be_bytes(value[..].try_into().unwrap()))
+        };
+        assert_eq!(get(b"a"), Some(2));
+        assert_eq!(get(b"b"), Some(11));
+        assert_eq!(get(b"c"), None);
+    };
+    {
+        let storage = LsmStorage::open_with_options(&dir, options.clone()).unwrap();
+        // The writes of a key in a batch apply in order, so that no operand is lost, and the
+        // batch still takes a single sequence number.
+        let seq = storage.latest_seq();
+        storage
+            .write(
+                WriteBatch::new()
+                    .merge(b"a", &1u64.to_be_bytes())
+                    .merge(b"a", &1u64.to_be_bytes()),
+            )
+            .unwrap();
+        storage
+            .write(
+                WriteBatch::new()
+                    .put(b"b", &10u64.to_be_bytes())
+                    .merge(b"b", &1u64.to_be_bytes())
+                    .merge(b"c", &1u64.to_be_bytes())
+                    .delete(b"c"),
+            )
+            .unwrap();
+        assert_eq!(storage.latest_seq(), seq + 2);
+        check(&storage);
+    }
+    // The batches are replayed from the WAL in the same order, and stay so once flushed.
+    let storage = LsmStorage::open_with_options(&dir, options).unwrap();
+    check(&storage);
+    storage.sync().unwrap();
+    check(&storage);
+}
+
+#[test]
+fn test_merge_append_scan() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, append_options()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.merge(b"b", b"1").unwrap();
+    storage.put(b"c", b"1").unwrap();
+    storage.sync().unwrap();
+    let snapshot = storage.snapshot();
+    storage
+        .write(WriteBatch::new().merge(b"a", b"2").merge(b"b", b"2"))
+        .unwrap();
+    storage.merge(b"a", b"3").unwrap();
+    storage.delete(b"c").unwrap();
+    storage.merge(b"d", b"1").unwrap();
+
+    let expected = |pairs: &[(&str, &str)]| -> Vec<(Vec<u8>, Vec<u8>)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            .collect()
+    };
+    assert_eq!(
+        collect(storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+        expected(&[("a", "123"), ("b", "12"), ("d", "1")])
+    );
+    assert_eq!(
+        collect(
+            storage
+                .scan(Bound::Excluded(b"a"), Bound::Included(b"b"))
+                .unwrap()
+        ),
+        expected(&[("b", "12")])
+    );
+    assert_eq!(
+        collect(snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+        expected(&[("a", "1"), ("b", "1"), ("c", "1")])
+    );
+    assert_eq!(&storage.get(b"a").unwrap().unwrap()[..], b"123");
+    assert_eq!(&snapshot.get(b"b").unwrap().unwrap()[..], b"1");
+}
+
+#[test]
+fn test_merge_compaction() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, append_options()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.merge(b"b", b"1").unwrap();
+    storage.sync().unwrap();
+    storage.merge(b"a", b"2").unwrap();
+    storage.merge(b"b", b"2").unwrap();
+    storage.sync().unwrap();
+    let snapshot = storage.snapshot();
+    storage.merge(b"a", b"3").unwrap();
+    storage.merge(b"b", b"3").unwrap();
+    storage.sync().unwrap();
+
+    // The operands the snapshot sees are combined separately from the newer ones.
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    assert_eq!(num_merge_operands(&storage), 2);
+    assert_eq!(&storage.get(b"a").unwrap().unwrap()[..], b"123");
+    assert_eq!(&storage.get(b"b").unwrap().unwrap()[..], b"123");
+    assert_eq!(&snapshot.get(b"a").unwrap().unwrap()[..], b"12");
+    assert_eq!(&snapshot.get(b"b").unwrap().unwrap()[..], b"12");
+
+    drop(snapshot);
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    assert_eq!(num_merge_operands(&storage), 0);
+    assert_eq!(
+        collect(storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+        vec![
+            (b"a".to_vec(), b"123".to_vec()),
+            (b"b".to_vec(), b"123".to_vec())
+        ]
+    );
+}
+
+#[test]
+fn test_merge_without_operator() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    assert!(storage.merge(b"a", b"1").is_err());
+    assert!(storage.write(WriteBatch::new().merge(b"a", b"1")).is_err());
+    assert!(storage.get(b"a").unwrap().is_none());
+}
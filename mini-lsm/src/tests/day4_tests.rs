@@ -123,6 +123,53 @@ fn test_storage_get_after_sync() {
     assert!(storage.get(b"2").unwrap().is_none());
 }
 
+#[test]
+fn test_storage_get_tombstone_after_sync() {
+    use crate::lsm_storage::LsmStorage;
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    storage.put(b"3", b"23333").unwrap();
+    storage.sync().unwrap();
+    storage.delete(b"1").unwrap();
+    storage.sync().unwrap();
+    assert!(storage.get(b"0").unwrap().is_none());
+    assert!(storage.get(b"1").unwrap().is_none());
+    assert!(storage.get(b"2").unwrap().is_none());
+    assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"23333");
+    assert!(storage.get(b"4").unwrap().is_none());
+}
+
+#[test]
+fn test_storage_empty_value() {
+    use crate::lsm_storage::LsmStorage;
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"1", b"").unwrap();
+    storage.put(b"2", b"2333").unwrap();
+    storage.put(b"3", b"").unwrap();
+    storage.delete(b"3").unwrap();
+    for sync in [false, true] {
+        if sync {
+            storage.sync().unwrap();
+        }
+        assert_eq!(storage.get(b"1").unwrap(), Some(Bytes::new()));
+        assert!(storage.get(b"3").unwrap().is_none());
+        check_iter_result(
+            storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            vec![
+                (Bytes::from("1"), Bytes::new()),
+                (Bytes::from("2"), Bytes::from("2333")),
+            ],
+        );
+    }
+    // A delete in the memtable hides an empty value in an SST.
+    storage.delete(b"1").unwrap();
+    assert!(storage.get(b"1").unwrap().is_none());
+    storage.put(b"2", b"").unwrap();
+    assert_eq!(storage.get(b"2").unwrap(), Some(Bytes::new()));
+}
+
 #[test]
 fn test_storage_scan_memtable_1_after_sync() {
     use crate::lsm_storage::LsmStorage;
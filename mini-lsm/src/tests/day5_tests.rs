@@ -0,0 +1,298 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+use super::harness::{check_storage, compaction_options, key_of};
+use crate::compact::{CompactionOptions, LeveledCompactionOptions};
+use crate::lsm_storage::{BackgroundError, LsmStorage, LsmStorageOptions};
+use crate::write_stall::{WriteStallCondition, WriteStallOptions, WriteStallReason};
+
+#[test]
+fn test_storage_leveled_compaction() {
+    let dir = tempdir().unwrap();
+    let mut expected = BTreeMap::new();
+    {
+        let storage = LsmStorage::open_with_options(&dir, compaction_options()).unwrap();
+        for round in 0..10 {
+            for i in 0..100 {
+                let idx = (i * 7 + round * 13) % 300;
+                let value = format!("value_{}_{}", idx, round).into_bytes();
+                storage.put(&key_of(idx), &value).unwrap();
+                expected.insert(key_of(idx), value);
+            }
+            for i in 0..10 {
+                let idx = (i * 31 + round * 17) % 300;
+                storage.delete(&key_of(idx)).unwrap();
+                expected.remove(&key_of(idx));
+            }
+            storage.sync().unwrap();
+            storage.compact().unwrap();
+
+            let snapshot = storage.core().inner.read().clone();
+            assert!(snapshot.l0_sstables.len() < 2);
+            for level in &snapshot.levels {
+                for window in level.windows(2) {
+                    assert!(window[0].last_key() < window[1].first_key());
+                }
+            }
+        }
+        let snapshot = storage.core().inner.read().clone();
+        assert!(snapshot.levels[1].len() + snapshot.levels[2].len() > 0);
+        check_storage(&storage, &expected);
+    }
+
+    // The compacted levels are restored from the manifest.
+    let storage = LsmStorage::open_with_options(&dir, compaction_options()).unwrap();
+    check_storage(&storage, &expected);
+    let mut sst_files = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".sst"))
+        .count();
+    let snapshot = storage.core().inner.read().clone();
+    sst_files -= snapshot.l0_sstables.len();
+    for level in &snapshot.levels {
+        sst_files -= level.len();
+    }
+    assert_eq!(sst_files, 0, "compacted SSTs should be removed");
+}
+
+#[test]
+fn test_storage_compaction_drops_tombstones_in_bottom_level() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, compaction_options()).unwrap();
+    for i in 0..100 {
+        storage.put(&key_of(i), b"value").unwrap();
+    }
+    storage.sync().unwrap();
+    for i in 0..100 {
+        storage.delete(&key_of(i)).unwrap();
+    }
+    storage.sync().unwrap();
+    storage.compact().unwrap();
+    let snapshot = storage.core().inner.read().clone();
+    assert!(snapshot.l0_sstables.is_empty());
+    assert!(snapshot.levels.iter().all(|level| level.is_empty()));
+    check_storage(&storage, &BTreeMap::new());
+}
+
+/// A xorshift generator, so that the random tests are reproducible.
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+#[test]
+fn test_storage_get_random() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(
+        &dir,
+        LsmStorageOptions {
+            write_buffer_size: 1024,
+            ..compaction_options()
+        },
+    )
+    .unwrap();
+    let mut model = BTreeMap::new();
+    let mut rng = 0x2545f4914f6cdd1d;
+    for op in 0..5000 {
+        // Odd keys are never written, so that lookups also hit keys between existing ones.
+        let idx = next_random(&mut rng) as usize % 200 * 2;
+        match next_random(&mut rng) % 10 {
+            0..=5 => {
+                let value = format!("value_{}_{}", idx, op).into_bytes();
+                storage.put(&key_of(idx), &value).unwrap();
+                model.insert(key_of(idx), value);
+            }
+            6..=8 => {
+                storage.delete(&key_of(idx)).unwrap();
+                model.remove(&key_of(idx));
+            }
+            _ => storage.sync().unwrap(),
+        }
+        if op % 500 == 0 {
+            for idx in 0..401 {
+                let value = storage.get(&key_of(idx)).unwrap();
+                assert_eq!(
+                    value.as_deref(),
+                    model.get(&key_of(idx)).map(|v| &v[..]),
+                    "key {} after op {}",
+                    idx,
+                    op
+                );
+            }
+        }
+    }
+    storage.sync().unwrap();
+    storage.compact().unwrap();
+    for idx in 0..401 {
+        let value = storage.get(&key_of(idx)).unwrap();
+        assert_eq!(value.as_deref(), model.get(&key_of(idx)).map(|v| &v[..]));
+    }
+    check_storage(&storage, &model);
+}
+
+#[test]
+fn test_storage_close() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, compaction_options()).unwrap();
+    for i in 0..100 {
+        storage.put(&key_of(i), b"value").unwrap();
+    }
+    storage.close().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // The current memtable is flushed on close, so there is nothing to replay from the WAL.
+    let storage = LsmStorage::open_with_options(&dir, compaction_options()).unwrap();
+    let snapshot = storage.core().inner.read().clone();
+    assert!(snapshot.imm_memtables.is_empty());
+    for i in 0..100 {
+        assert_eq!(&storage.get(&key_of(i)).unwrap().unwrap()[..], b"value");
+    }
+}
+
+#[test]
+fn test_storage_background_error() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        write_buffer_size: 1024,
+        ..compaction_options()
+    };
+    let storage = LsmStorage::open_with_options(&dir, options).unwrap();
+    // Make the flush of the current memtable fail, by taking the path of its SST.
+    let memtable_id = storage.core().inner.read().memtable.id();
+    std::fs::create_dir(dir.path().join(format!("{:05}.sst", memtable_id))).unwrap();
+    for i in 0..100 {
+        if storage.put(&key_of(i), b"value").is_err() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    let err = storage.put(b"key", b"value").unwrap_err();
+    assert!(err.downcast_ref::<BackgroundError>().is_some());
+    assert!(storage
+        .sync()
+        .unwrap_err()
+        .downcast_ref::<BackgroundError>()
+        .is_some());
+    assert!(storage.close().is_err());
+    // The data written before the error is still readable.
+    assert_eq!(&storage.get(&key_of(0)).unwrap().unwrap()[..], b"value");
+}
+
+#[test]
+fn test_storage_freeze_memtable_by_size() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        write_buffer_size: 1024,
+        write_stall_options: WriteStallOptions {
+            imm_memtables_slowdown_trigger: 1,
+            imm_memtables_stop_trigger: 2,
+            ..Default::default()
+        },
+        ..compaction_options()
+    };
+    let storage = LsmStorage::open_with_options(&dir, options).unwrap();
+    let mut expected = BTreeMap::new();
+    for i in 0..1000 {
+        let value = format!("value_{}", i).into_bytes();
+        storage.put(&key_of(i % 400), &value).unwrap();
+        expected.insert(key_of(i % 400), value);
+
+        let snapshot = storage.core().inner.read().clone();
+        assert!(snapshot.memtable.approximate_size() < 1024);
+        assert!(snapshot.imm_memtables.len() <= 2);
+    }
+    let snapshot = storage.core().inner.read().clone();
+    assert!(snapshot.imm_memtables.len() + snapshot.l0_sstables.len() > 0);
+    check_storage(&storage, &expected);
+
+    // Writes do not stall forever once the flush thread is stopped.
+    storage.stop_background_threads().unwrap();
+    for i in 0..1000 {
+        storage.put(&key_of(i), b"value").unwrap();
+    }
+    let snapshot = storage.core().inner.read().clone();
+    assert!(snapshot.imm_memtables.len() <= 2);
+}
+
+#[test]
+fn test_storage_write_stall() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        write_stall_options: WriteStallOptions {
+            level0_slowdown_writes_trigger: 1,
+            level0_stop_writes_trigger: 2,
+            ..Default::default()
+        },
+        ..compaction_options()
+    };
+    let storage = LsmStorage::open_with_options(&dir, options).unwrap();
+    assert_eq!(storage.write_stall_condition(), WriteStallCondition::Normal);
+
+    // Stop the background threads so that L0 SSTs pile up.
+    storage.stop_background_threads().unwrap();
+    storage.put(b"1", b"233").unwrap();
+    storage.sync().unwrap();
+    assert_eq!(
+        storage.write_stall_condition(),
+        WriteStallCondition::Delayed(WriteStallReason::Level0Files)
+    );
+    storage.put(b"2", b"2333").unwrap();
+    storage.sync().unwrap();
+    assert_eq!(
+        storage.write_stall_condition(),
+        WriteStallCondition::Stopped(WriteStallReason::Level0Files)
+    );
+
+    // The stopped write compacts L0 by itself, as there is no compaction thread.
+    storage.put(b"3", b"23333").unwrap();
+    assert_eq!(storage.write_stall_condition(), WriteStallCondition::Normal);
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"233");
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"2333");
+    assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"23333");
+}
+
+#[test]
+fn test_storage_write_stall_without_compaction_progress() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        // Without levels, compaction can never bring down the number of L0 SSTs.
+        compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 0,
+            base_level_size: 1024,
+            level_size_multiplier: 2,
+        }),
+        write_stall_options: WriteStallOptions {
+            level0_slowdown_writes_trigger: 1,
+            level0_stop_writes_trigger: 2,
+            ..Default::default()
+        },
+        ..compaction_options()
+    };
+    let storage = Arc::new(LsmStorage::open_with_options(&dir, options).unwrap());
+    for key in [b"1", b"2"] {
+        storage.put(key, b"value").unwrap();
+        storage.sync().unwrap();
+    }
+    assert_eq!(
+        storage.write_stall_condition(),
+        WriteStallCondition::Stopped(WriteStallReason::Level0Files)
+    );
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let writer = {
+        let storage = storage.clone();
+        std::thread::spawn(move || tx.send(storage.put(b"3", b"value").is_ok()).unwrap())
+    };
+    assert!(rx.recv_timeout(Duration::from_secs(5)).unwrap());
+    writer.join().unwrap();
+    assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"value");
+}
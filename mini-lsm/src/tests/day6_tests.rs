@@ -0,0 +1,87 @@
+use tempfile::tempdir;
+
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::wal::WalSyncPolicy;
+
+#[test]
+fn test_storage_recover_from_wal() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir).unwrap();
+        storage.put(b"1", b"233").unwrap();
+        storage.put(b"2", b"2333").unwrap();
+        storage.put(b"3", b"23333").unwrap();
+        storage.delete(b"2").unwrap();
+        storage.crash();
+    }
+    let storage = LsmStorage::open(&dir).unwrap();
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"233");
+    assert!(storage.get(b"2").unwrap().is_none());
+    assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"23333");
+}
+
+#[test]
+fn test_storage_recover_multiple_wals() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        wal_sync_policy: WalSyncPolicy::Group(16),
+        ..Default::default()
+    };
+    {
+        let storage = LsmStorage::open_with_options(&dir, options.clone()).unwrap();
+        storage.put(b"1", b"233").unwrap();
+        storage.put(b"2", b"2333").unwrap();
+        storage.crash();
+    }
+    {
+        let storage = LsmStorage::open_with_options(&dir, options.clone()).unwrap();
+        storage.put(b"1", b"2333333").unwrap();
+        assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"2333333");
+        assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"2333");
+        storage.crash();
+    }
+    let storage = LsmStorage::open_with_options(&dir, options).unwrap();
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"2333333");
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"2333");
+    storage.sync().unwrap();
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"2333333");
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"2333");
+    // Only the WAL of the current memtable is left after the flush.
+    let wals = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".wal"))
+        .count();
+    assert_eq!(wals, 1);
+}
+
+#[test]
+fn test_storage_recover_from_manifest() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir).unwrap();
+        storage.put(b"1", b"233").unwrap();
+        storage.put(b"2", b"2333").unwrap();
+        storage.sync().unwrap();
+        storage.put(b"3", b"23333").unwrap();
+        storage.put(b"1", b"2").unwrap();
+        storage.sync().unwrap();
+        storage.put(b"4", b"233333").unwrap();
+        storage.crash();
+    }
+    {
+        let storage = LsmStorage::open(&dir).unwrap();
+        assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"2");
+        assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"2333");
+        assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"23333");
+        assert_eq!(&storage.get(b"4").unwrap().unwrap()[..], b"233333");
+        storage.sync().unwrap();
+        storage.put(b"2", b"23").unwrap();
+        storage.crash();
+    }
+    let storage = LsmStorage::open(&dir).unwrap();
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"2");
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"23");
+    assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"23333");
+    assert_eq!(&storage.get(b"4").unwrap().unwrap()[..], b"233333");
+}
@@ -0,0 +1,136 @@
+use tempfile::tempdir;
+
+use super::harness::key_of;
+use crate::lsm_storage::{BloomFilterStats, LsmStorage, LsmStorageOptions};
+
+#[test]
+fn test_storage_bloom_filter_skips_ssts() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(
+        &dir,
+        LsmStorageOptions {
+            block_size: 64,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    // Every other key goes into a different L0 SST.
+    for sst in 0..2 {
+        for i in 0..100 {
+            let idx = i * 2 + sst;
+            storage.put(&key_of(idx), &key_of(idx)).unwrap();
+        }
+        storage.sync().unwrap();
+    }
+    // Key 0 is out of the range of the newer SST, so it is skipped without checking the filter.
+    for idx in 1..200 {
+        assert_eq!(
+            &storage.get(&key_of(idx)).unwrap().unwrap()[..],
+            key_of(idx)
+        );
+    }
+    let stats = storage.bloom_filter_stats();
+    // The newer SST is searched for every key, the older one only for the keys not in the newer
+    // one, where it always has the key.
+    assert_eq!(stats.true_positive, 199);
+    assert_eq!(stats.negative + stats.false_positive, 99);
+    assert!(stats.negative > 89, "{:?}", stats);
+}
+
+#[test]
+fn test_storage_bloom_filter_newer_versions() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(&key_of(0), b"value").unwrap();
+    let seq = storage.latest_seq();
+    storage.put(&key_of(1), b"value").unwrap();
+    storage.put(&key_of(2), b"value").unwrap();
+    storage.sync().unwrap();
+    // The SST has the key, only in a version newer than the lookup, so the filter was right.
+    assert!(storage.get_at_seq(&key_of(1), seq).unwrap().is_none());
+    assert_eq!(
+        storage.bloom_filter_stats(),
+        BloomFilterStats {
+            true_positive: 1,
+            ..Default::default()
+        }
+    );
+}
+
+#[test]
+fn test_storage_bloom_filter_disabled() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(
+        &dir,
+        LsmStorageOptions {
+            bloom_bits_per_key: 0,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    for i in 0..100 {
+        storage.put(&key_of(i * 2), b"value").unwrap();
+    }
+    storage.sync().unwrap();
+    // Keys between the first and the last key of the SST can only be ruled out by reading it.
+    for i in 0..99 {
+        assert!(storage.get(&key_of(i * 2 + 1)).unwrap().is_none());
+    }
+    // SSTs without a filter are not counted.
+    assert_eq!(storage.bloom_filter_stats(), BloomFilterStats::default());
+}
+
+#[test]
+fn test_storage_scan_skips_ssts_out_of_range() {
+    use std::ops::Bound;
+
+    use crate::iterators::StorageIterator;
+
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    for sst in 0..2 {
+        for i in 0..100 {
+            let idx = sst * 100 + i;
+            storage.put(&key_of(idx), &key_of(idx)).unwrap();
+        }
+        storage.sync().unwrap();
+    }
+    // Corrupt the first block of the newer SST, which holds keys from 100.
+    let snapshot = storage.core().inner.read().clone();
+    let id = snapshot.l0_sstables.last().unwrap().id();
+    let path = storage.core().path_of_sst(id);
+    let mut data = std::fs::read(&path).unwrap();
+    data[1] ^= 1;
+    std::fs::write(&path, data).unwrap();
+
+    let check_scan =
+        |lower: Bound<&[u8]>, upper: Bound<&[u8]>, expected: std::ops::Range<usize>| {
+            let mut iter = storage.scan(lower, upper).unwrap();
+            for idx in expected {
+                assert_eq!(iter.key(), key_of(idx));
+                iter.next().unwrap();
+            }
+            assert!(!iter.is_valid());
+        };
+    check_scan(Bound::Unbounded, Bound::Excluded(&key_of(100)), 0..100);
+    check_scan(
+        Bound::Included(&key_of(10)),
+        Bound::Included(&key_of(99)),
+        10..100,
+    );
+    check_scan(
+        Bound::Excluded(&key_of(10)),
+        Bound::Included(&key_of(20)),
+        11..21,
+    );
+    // The newer SST has to be read if the range overlaps with it.
+    let result = storage
+        .scan(Bound::Unbounded, Bound::Unbounded)
+        .and_then(|mut iter| {
+            while iter.is_valid() {
+                iter.next()?;
+            }
+            Ok(())
+        });
+    assert!(result.is_err());
+}
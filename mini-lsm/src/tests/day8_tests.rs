@@ -0,0 +1,185 @@
+use std::ops::Bound;
+
+use tempfile::tempdir;
+
+use super::harness::{collect, compaction_options, pairs};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::LsmStorage;
+use crate::write_batch::WriteBatch;
+
+/// Collect the key-value pairs of a scan of all keys as of `seq`.
+fn scan_at_seq(storage: &LsmStorage, seq: u64) -> Vec<(Vec<u8>, Vec<u8>)> {
+    collect(
+        storage
+            .scan_at_seq(Bound::Unbounded, Bound::Unbounded, seq)
+            .unwrap(),
+    )
+}
+
+#[test]
+fn test_storage_read_at_seq() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    assert_eq!(storage.latest_seq(), 0);
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    let seq = storage.latest_seq();
+    assert_eq!(seq, 2);
+    storage.put(b"a", b"2").unwrap();
+    storage.delete(b"b").unwrap();
+    storage.put(b"c", b"2").unwrap();
+
+    // The versions are kept in the memtables and in the SSTs they are flushed to.
+    for flush in [false, true] {
+        if flush {
+            storage.sync().unwrap();
+        }
+        assert_eq!(&storage.get_at_seq(b"a", seq).unwrap().unwrap()[..], b"1");
+        assert_eq!(&storage.get_at_seq(b"b", seq).unwrap().unwrap()[..], b"1");
+        assert!(storage.get_at_seq(b"c", seq).unwrap().is_none());
+        assert!(storage.get_at_seq(b"a", 0).unwrap().is_none());
+        assert_eq!(&storage.get(b"a").unwrap().unwrap()[..], b"2");
+        assert!(storage.get(b"b").unwrap().is_none());
+        assert_eq!(scan_at_seq(&storage, seq), pairs(&[("a", "1"), ("b", "1")]));
+        assert_eq!(scan_at_seq(&storage, 3), pairs(&[("a", "2"), ("b", "1")]));
+        assert_eq!(
+            scan_at_seq(&storage, storage.latest_seq()),
+            pairs(&[("a", "2"), ("c", "2")])
+        );
+    }
+}
+
+#[test]
+fn test_storage_scan_excluded_bound_with_versions() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    for value in ["1", "2", "3"] {
+        storage.put(b"a", value.as_bytes()).unwrap();
+        storage.put(b"b", value.as_bytes()).unwrap();
+        storage.sync().unwrap();
+    }
+    storage.put(b"a", b"4").unwrap();
+    let mut iter = storage
+        .scan(Bound::Excluded(b"a"), Bound::Unbounded)
+        .unwrap();
+    assert_eq!(iter.key(), b"b");
+    assert_eq!(iter.value(), b"3");
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_storage_seq_after_reopen() {
+    let dir = tempdir().unwrap();
+    let seq = {
+        let storage = LsmStorage::open(&dir).unwrap();
+        storage.put(b"a", b"1").unwrap();
+        storage.sync().unwrap();
+        let seq = storage.latest_seq();
+        storage.put(b"a", b"2").unwrap();
+        storage.close().unwrap();
+        seq
+    };
+    let storage = LsmStorage::open(&dir).unwrap();
+    assert_eq!(storage.latest_seq(), seq + 1);
+    assert_eq!(&storage.get_at_seq(b"a", seq).unwrap().unwrap()[..], b"1");
+    // New writes are newer than the ones before the restart.
+    storage.put(b"a", b"3").unwrap();
+    assert_eq!(storage.latest_seq(), seq + 2);
+    assert_eq!(&storage.get(b"a").unwrap().unwrap()[..], b"3");
+}
+
+#[test]
+fn test_storage_snapshot_ignores_later_writes() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    let snapshot = storage.snapshot();
+    assert_eq!(snapshot.seq(), storage.latest_seq());
+    storage.put(b"a", b"2").unwrap();
+    storage.delete(b"b").unwrap();
+    storage.put(b"c", b"2").unwrap();
+    let clone = snapshot.clone();
+    drop(snapshot);
+    for flush in [false, true] {
+        if flush {
+            storage.sync().unwrap();
+        }
+        assert_eq!(&clone.get(b"a").unwrap().unwrap()[..], b"1");
+        assert_eq!(&clone.get(b"b").unwrap().unwrap()[..], b"1");
+        assert!(clone.get(b"c").unwrap().is_none());
+        assert_eq!(
+            collect(clone.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+            pairs(&[("a", "1"), ("b", "1")])
+        );
+    }
+}
+
+#[test]
+fn test_storage_compaction_keeps_versions_of_snapshots() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, compaction_options()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    storage.sync().unwrap();
+    let snapshot = storage.snapshot();
+    let seq = snapshot.seq();
+    storage.put(b"a", b"2").unwrap();
+    storage.delete(b"b").unwrap();
+    storage.sync().unwrap();
+    storage.compact().unwrap();
+    assert!(storage.core().inner.read().l0_sstables.is_empty());
+    assert_eq!(&snapshot.get(b"a").unwrap().unwrap()[..], b"1");
+    assert_eq!(&snapshot.get(b"b").unwrap().unwrap()[..], b"1");
+    assert_eq!(&storage.get_at_seq(b"b", seq).unwrap().unwrap()[..], b"1");
+    assert_eq!(&storage.get(b"a").unwrap().unwrap()[..], b"2");
+    assert!(storage.get(b"b").unwrap().is_none());
+
+    // Once the snapshot is released, its versions are dropped by the next compaction.
+    drop(snapshot);
+    storage.put(b"a", b"3").unwrap();
+    storage.sync().unwrap();
+    storage.put(b"c", b"3").unwrap();
+    storage.sync().unwrap();
+    storage.compact().unwrap();
+    assert!(storage.core().inner.read().l0_sstables.is_empty());
+    assert!(storage.get_at_seq(b"a", seq).unwrap().is_none());
+    assert!(storage.get_at_seq(b"b", seq).unwrap().is_none());
+    assert_eq!(
+        scan_at_seq(&storage, storage.latest_seq()),
+        pairs(&[("a", "3"), ("c", "3")])
+    );
+}
+
+#[test]
+fn test_storage_write_batch() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir).unwrap();
+        storage.put(b"a", b"1").unwrap();
+        let seq = storage.latest_seq();
+        let mut batch = WriteBatch::new();
+        batch
+            .put(b"b", b"2")
+            .delete(b"a")
+            .put(b"c", b"2")
+            .put(b"b", b"3");
+        storage.write(&batch).unwrap();
+        // The whole batch is one version.
+        assert_eq!(storage.latest_seq(), seq + 1);
+        assert_eq!(scan_at_seq(&storage, seq), pairs(&[("a", "1")]));
+        assert_eq!(
+            scan_at_seq(&storage, seq + 1),
+            pairs(&[("b", "3"), ("c", "2")])
+        );
+        // An empty batch does not take a sequence number.
+        storage.write(&WriteBatch::new()).unwrap();
+        assert_eq!(storage.latest_seq(), seq + 1);
+    }
+    // The batch is replayed from the WAL.
+    let storage = LsmStorage::open(&dir).unwrap();
+    assert_eq!(storage.latest_seq(), 2);
+    assert_eq!(scan_at_seq(&storage, 1), pairs(&[("a", "1")]));
+    assert_eq!(scan_at_seq(&storage, 2), pairs(&[("b", "3"), ("c", "2")]));
+}
@@ -0,0 +1,220 @@
+use std::ops::Bound;
+
+use tempfile::tempdir;
+
+use super::harness::{collect, pairs};
+use crate::lsm_storage::LsmStorage;
+use crate::transaction::{IsolationLevel, Transaction, TransactionConflict};
+
+#[test]
+fn test_txn_read_own_writes() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    storage.put(b"d", b"1").unwrap();
+    storage.sync().unwrap();
+    storage.put(b"e", b"1").unwrap();
+
+    let txn = storage.begin_transaction();
+    txn.put(b"a", b"2");
+    txn.delete(b"b");
+    txn.put(b"c", b"2");
+    txn.delete(b"x");
+    // Writes after the start of the transaction are not seen.
+    storage.put(b"f", b"1").unwrap();
+    assert_eq!(&txn.get(b"a").unwrap().unwrap()[..], b"2");
+    assert!(txn.get(b"b").unwrap().is_none());
+    assert_eq!(&txn.get(b"c").unwrap().unwrap()[..], b"2");
+    assert_eq!(
+        collect(txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+        pairs(&[("a", "2"), ("c", "2"), ("d", "1"), ("e", "1")])
+    );
+    assert_eq!(
+        collect(
+            txn.scan(Bound::Excluded(b"a"), Bound::Included(b"d"))
+                .unwrap()
+        ),
+        pairs(&[("c", "2"), ("d", "1")])
+    );
+    assert!(collect(
+        txn.scan(Bound::Excluded(b"c"), Bound::Excluded(b"c"))
+            .unwrap()
+    )
+    .is_empty());
+    // Nothing is visible to others before the commit.
+    assert_eq!(&storage.get(b"a").unwrap().unwrap()[..], b"1");
+    txn.commit().unwrap();
+    assert_eq!(
+        collect(storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+        pairs(&[("a", "2"), ("c", "2"), ("d", "1"), ("e", "1"), ("f", "1")])
+    );
+}
+
+#[test]
+fn test_txn_conflict() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"counter", b"0").unwrap();
+
+    // Two read-modify-writes of the same key: the second to commit fails.
+    let txn1 = storage.begin_transaction();
+    let txn2 = storage.begin_transaction();
+    for txn in [&txn1, &txn2] {
+        let value = txn.get(b"counter").unwrap().unwrap();
+        let value: u32 = std::str::from_utf8(&value).unwrap().parse().unwrap();
+        txn.put(b"counter", (value + 1).to_string().as_bytes());
+    }
+    txn1.commit().unwrap();
+    let err = txn2.commit().unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<TransactionConflict>().unwrap().key,
+        &b"counter"[..]
+    );
+    assert_eq!(&storage.get(b"counter").unwrap().unwrap()[..], b"1");
+
+    // A write outside of a transaction conflicts too, also once it is flushed.
+    let txn = storage.begin_transaction();
+    assert!(txn.get(b"missing").unwrap().is_none());
+    txn.put(b"other", b"1");
+    storage.put(b"missing", b"1").unwrap();
+    storage.sync().unwrap();
+    assert!(txn.commit().is_err());
+    assert!(storage.get(b"other").unwrap().is_none());
+
+    // Keys read through a scan are checked as well.
+    let txn = storage.begin_transaction();
+    assert_eq!(
+        collect(txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap()).len(),
+        2
+    );
+    txn.put(b"other", b"1");
+    storage.delete(b"counter").unwrap();
+    assert!(txn.commit().is_err());
+}
+
+#[test]
+fn test_txn_no_conflict() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"a", b"0").unwrap();
+    storage.put(b"b", b"0").unwrap();
+
+    // Writes to keys the transaction did not read do not conflict.
+    let txn1 = storage.begin_transaction();
+    let txn2 = storage.begin_transaction();
+    assert_eq!(&txn1.get(b"a").unwrap().unwrap()[..], b"0");
+    txn1.put(b"a", b"1");
+    assert_eq!(&txn2.get(b"b").unwrap().unwrap()[..], b"0");
+    txn2.put(b"b", b"1");
+    txn2.put(b"a", b"2");
+    txn1.commit().unwrap();
+    txn2.commit().unwrap();
+    assert_eq!(&storage.get(b"a").unwrap().unwrap()[..], b"2");
+    assert_eq!(&storage.get(b"b").unwrap().unwrap()[..], b"1");
+
+    // A transaction that is dropped writes nothing.
+    let txn = storage.begin_transaction();
+    txn.put(b"c", b"1");
+    drop(txn);
+    assert!(storage.get(b"c").unwrap().is_none());
+}
+
+/// Book a slot unless two are booked already, based on a scan of the bookings.
+fn book(txn: &Transaction, slot: &str) {
+    let bookings = collect(
+        txn.scan(Bound::Included(b"booking/"), Bound::Excluded(b"booking0"))
+            .unwrap(),
+    );
+    if bookings.len() < 2 {
+        txn.put(format!("booking/{}", slot).as_bytes(), b"1");
+    }
+}
+
+#[test]
+fn test_txn_serializable_detects_phantoms() {
+    for isolation in [IsolationLevel::Snapshot, IsolationLevel::Serializable] {
+        let dir = tempdir().unwrap();
+        let storage = LsmStorage::open(&dir).unwrap();
+        storage.put(b"booking/1", b"1").unwrap();
+        storage.put(b"other", b"1").unwrap();
+
+        let txn1 = storage.begin_transaction_with_isolation(isolation);
+        let txn2 = storage.begin_transaction_with_isolation(isolation);
+        book(&txn1, "2");
+        book(&txn2, "3");
+        txn1.commit().unwrap();
+        let result = txn2.commit();
+        match isolation {
+            // Neither transaction read a key the other wrote, so both commit.
+            IsolationLevel::Snapshot => {
+                result.unwrap();
+                assert_eq!(
+                    collect(
+                        storage
+                            .scan(Bound::Included(b"booking/"), Bound::Excluded(b"booking0"))
+                            .unwrap()
+                    )
+                    .len(),
+                    3
+                );
+            }
+            IsolationLevel::Serializable => {
+                assert_eq!(
+                    result
+                        .unwrap_err()
+                        .downcast_ref::<TransactionConflict>()
+                        .unwrap()
+                        .key,
+                    &b"booking/2"[..]
+                );
+                assert!(storage.get(b"booking/3").unwrap().is_none());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_txn_serializable_ignores_writes_outside_ranges() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"b", b"1").unwrap();
+    let txn = storage.begin_transaction_with_isolation(IsolationLevel::Serializable);
+    assert_eq!(
+        collect(
+            txn.scan(Bound::Excluded(b"a"), Bound::Excluded(b"c"))
+                .unwrap()
+        ),
+        pairs(&[("b", "1")])
+    );
+    txn.put(b"d", b"1");
+    // The bounds of the scan are excluded.
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"c", b"1").unwrap();
+    storage.sync().unwrap();
+    txn.commit().unwrap();
+    assert_eq!(&storage.get(b"d").unwrap().unwrap()[..], b"1");
+}
+
+#[test]
+fn test_txn_write_log_is_pruned() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    // Writes are not logged without a live transaction.
+    storage.put(b"a", b"1").unwrap();
+    assert_eq!(storage.core().write_log.len(), 0);
+
+    let txn1 = storage.begin_transaction();
+    storage.put(b"a", b"2").unwrap();
+    let txn2 = storage.begin_transaction();
+    storage.put(b"b", b"2").unwrap();
+    assert_eq!(storage.core().write_log.len(), 2);
+
+    // The writes seen by all the live transactions are dropped on the next write.
+    drop(txn1);
+    storage.put(b"c", b"2").unwrap();
+    assert_eq!(storage.core().write_log.len(), 2);
+    drop(txn2);
+    storage.put(b"d", b"2").unwrap();
+    assert_eq!(storage.core().write_log.len(), 0);
+}
@@ -0,0 +1,78 @@
+//! Helpers shared by the storage tests.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use bytes::Bytes;
+
+use crate::compact::{CompactionOptions, LeveledCompactionOptions};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+
+/// Leveled compaction of L0 once it has 2 SSTs, into 3 levels from 1KB.
+pub fn leveled_compaction() -> CompactionOptions {
+    CompactionOptions::Leveled(LeveledCompactionOptions {
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+        base_level_size: 1024,
+        level_size_multiplier: 2,
+    })
+}
+
+/// Options with small blocks and SSTs, so that a few hundred keys make many SSTs to compact with
+/// `compaction_options`.
+pub fn options_with_compaction(compaction_options: CompactionOptions) -> LsmStorageOptions {
+    LsmStorageOptions {
+        block_size: 64,
+        target_sst_size: 256,
+        compaction_options,
+        ..Default::default()
+    }
+}
+
+/// Options with small blocks and SSTs, and leveled compaction.
+pub fn compaction_options() -> LsmStorageOptions {
+    options_with_compaction(leveled_compaction())
+}
+
+pub fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+/// Check that the storage contains exactly `expected`, through both scans and point lookups.
+pub fn check_storage(storage: &LsmStorage, expected: &BTreeMap<Vec<u8>, Vec<u8>>) {
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for (key, value) in expected {
+        assert!(iter.is_valid());
+        assert_eq!(
+            Bytes::copy_from_slice(iter.key()),
+            Bytes::copy_from_slice(key)
+        );
+        assert_eq!(
+            Bytes::copy_from_slice(iter.value()),
+            Bytes::copy_from_slice(value)
+        );
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    for (key, value) in expected {
+        assert_eq!(&storage.get(key).unwrap().unwrap()[..], &value[..]);
+    }
+}
+
+/// Collect the key-value pairs of an iterator.
+pub fn collect(mut iter: impl StorageIterator) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut result = Vec::new();
+    while iter.is_valid() {
+        result.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.next().unwrap();
+    }
+    result
+}
+
+pub fn pairs(pairs: &[(&str, &str)]) -> Vec<(Vec<u8>, Vec<u8>)> {
+    pairs
+        .iter()
+        .map(|(key, value)| (key.as_bytes().to_vec(), value.as_bytes().to_vec()))
+        .collect()
+}
@@ -0,0 +1,349 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use crate::iterators::StorageIterator;
+use crate::key::MAX_SEQ;
+use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::lsm_storage::LsmStorageCore;
+use crate::mem_table::map_bound;
+use crate::snapshot::{Snapshot, SnapshotList};
+use crate::value::ValueKind;
+use crate::write_batch::WriteBatch;
+
+/// The error a transaction fails to commit with when a key it read was written by someone else
+/// after the transaction started.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionConflict {
+    /// The key that was written.
+    pub key: Bytes,
+}
+
+impl std::fmt::Display for TransactionConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "transaction conflicts with a write of key {:?}",
+            self.key
+        )
+    }
+}
+
+impl std::error::Error for TransactionConflict {}
+
+/// Which writes of others a transaction checks for on commit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IsolationLevel {
+    /// Check the keys the transaction read. A key added to a range the transaction scanned is not
+    /// detected, so a decision based on what a scan did not find may be stale.
+    #[default]
+    Snapshot,
+    /// Check the ranges the transaction scanned as well, so that the committed transactions are
+    /// serializable.
+    Serializable,
+}
+
+/// The keys of the recent writes, for transactions to check their reads against on commit, so
+/// that the cost of a commit does not grow with the size of the storage.
+///
+/// Only the writes that a live transaction may conflict with are kept, that is the writes after the
+/// read sequence number of the oldest live transaction.
+#[derive(Default)]
+pub(crate) struct WriteLog {
+    /// The read sequence numbers of the live transactions.
+    transactions: SnapshotList,
+    /// The keys of each write, by its sequence number.
+    writes: Mutex<BTreeMap<u64, Vec<Bytes>>>,
+}
+
+impl WriteLog {
+    /// Log the keys of `batch`, written at `seq`. Must be called with the write lock held, after
+    /// `seq` is published, so that a transaction that starts later reads at `seq` or after.
+    pub(crate) fn add(&self, seq: u64, batch: &WriteBatch) {
+        let oldest = self.transactions.oldest();
+        let mut writes = self.writes.lock();
+        match oldest {
+            // The transactions read at their sequence number, so the writes up to it do not
+            // conflict with them.
+            Some(oldest) => {
+                *writes = writes.split_off(&(oldest + 1));
+                let keys = batch.entries().iter().map(|entry| entry.key.clone());
+                writes.insert(seq, keys.collect());
+            }
+            None => writes.clear(),
+        }
+    }
+
+    /// Find a key that was written after the sequence number `seq` and is in `reads`, either as a
+    /// key or in a range.
+    fn find_write_after(&self, reads: &ReadSet, seq: u64) -> Option<Bytes> {
+        let writes = self.writes.lock();
+        writes
+            .range(seq + 1..)
+            .flat_map(|(_, written)| written)
+            .find(|key| {
+                reads.keys.contains(*key) || reads.ranges.iter().any(|range| range.contains(*key))
+            })
+            .cloned()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.writes.lock().len()
+    }
+}
+
+/// What a transaction read from the storage.
+#[derive(Default)]
+struct ReadSet {
+    keys: BTreeSet<Bytes>,
+    /// The ranges scanned, only recorded in [`IsolationLevel::Serializable`].
+    ranges: Vec<(Bound<Bytes>, Bound<Bytes>)>,
+}
+
+/// An optimistic transaction.
+///
+/// Reads see the storage as of the start of the transaction, together with the transaction's own
+/// writes. Writes are kept in the transaction until it is committed. The commit fails with a
+/// [`TransactionConflict`] if a key the transaction read was written after it started, so that a
+/// read-modify-write never loses an update. Dropping a transaction without committing it discards
+/// its writes.
+pub struct Transaction {
+    core: Arc<LsmStorageCore>,
+    snapshot: Snapshot,
+    isolation: IsolationLevel,
+    writes: Mutex<BTreeMap<Bytes, (ValueKind, Bytes)>>,
+    reads: Arc<Mutex<ReadSet>>,
+}
+
+impl Transaction {
+    pub(crate) fn new(core: Arc<LsmStorageCore>, isolation: IsolationLevel) -> Self {
+        // Register the transaction before it gets its sequence number, so that the write log keeps
+        // all the writes after it.
+        let mut snapshot = None;
+        core.write_log.transactions.register(|| {
+            let new_snapshot = Snapshot::new(core.clone());
+            let seq = new_snapshot.seq();
+            snapshot = Some(new_snapshot);
+            seq
+        });
+        Self {
+            snapshot: snapshot.unwrap(),
+            core,
+            isolation,
+            writes: Mutex::new(BTreeMap::new()),
+            reads: Arc::new(Mutex::new(ReadSet::default())),
+        }
+    }
+
+    /// Get the sequence number the transaction reads at.
+    pub fn read_seq(&self) -> u64 {
+        self.snapshot.seq()
+    }
+
+    /// Get a key, as written by the transaction or as of its start.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        if let Some((kind, value)) = self.writes.lock().get(key) {
+            return match kind {
+                ValueKind::Put => Ok(Some(value.clone())),
+                ValueKind::Delete => Ok(None),
+                ValueKind::Merge => unreachable!("transactions do not merge"),
+            };
+        }
+        // A missing key is a read too, since writing it later changes the result.
+        self.reads.lock().keys.insert(Bytes::copy_from_slice(key));
+        self.snapshot.get(key)
+    }
+
+    /// Create an iterator over a range of keys, as written by the transaction or as of its start.
+    /// The keys it produces from the storage are read by the transaction, and so is the whole
+    /// range in [`IsolationLevel::Serializable`].
+    pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TransactionIterator> {
+        if self.isolation == IsolationLevel::Serializable {
+            self.reads
+                .lock()
+                .ranges
+                .push((map_bound(lower), map_bound(upper)));
+        }
+        let writes = if is_empty_range(lower, upper) {
+            Vec::new()
+        } else {
+            self.writes
+                .lock()
+                .range((map_bound(lower), map_bound(upper)))
+                .map(|(key, (kind, value))| (key.clone(), *kind, value.clone()))
+                .collect()
+        };
+        TransactionIterator::new(
+            writes,
+            self.snapshot.scan(lower, upper)?,
+            self.reads.clone(),
+        )
+    }
+
+    /// Put a key-value pair. It is visible to the transaction at once, and to others after the
+    /// commit.
+    pub fn put(&self, key: &[u8], value: &[u8]) {
+        assert!(!key.is_empty(), "key cannot be empty");
+
+        self.writes.lock().insert(
+            Bytes::copy_from_slice(key),
+            (ValueKind::Put, Bytes::copy_from_slice(value)),
+        );
+    }
+
+    /// Delete a key. It is visible to the transaction at once, and to others after the commit.
+    pub fn delete(&self, key: &[u8]) {
+        assert!(!key.is_empty(), "key cannot be empty");
+
+        self.writes.lock().insert(
+            Bytes::copy_from_slice(key),
+            (ValueKind::Delete, Bytes::new()),
+        );
+    }
+
+    /// Write all writes of the transaction atomically, unless a key it read, or a key in a range
+    /// it scanned in [`IsolationLevel::Serializable`], was written after it started. A transaction
+    /// without writes always commits, since its reads are all as of its start.
+    pub fn commit(self) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        for (key, (kind, value)) in self.writes.lock().iter() {
+            match kind {
+                ValueKind::Put => batch.put(key, value),
+                ValueKind::Delete => batch.delete(key),
+                ValueKind::Merge => unreachable!("transactions do not merge"),
+            };
+        }
+        let reads = self.reads.lock();
+        let read_seq = self.read_seq();
+        self.core.write_with_check(&batch, || {
+            match self.core.write_log.find_write_after(&reads, read_seq) {
+                Some(key) => Err(TransactionConflict { key }.into()),
+                None => Ok(()),
+            }
+        })
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        self.core.write_log.transactions.release(self.read_seq());
+    }
+}
+
+/// Check if no key can be between `lower` and `upper`. `BTreeMap::range` panics on such a range.
+fn is_empty_range(lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> bool {
+    match (lower, upper) {
+        (Bound::Excluded(lower), Bound::Excluded(upper)) => lower >= upper,
+        (
+            Bound::Included(lower) | Bound::Excluded(lower),
+            Bound::Included(upper) | Bound::Excluded(upper),
+        ) => lower > upper,
+        _ => false,
+    }
+}
+
+/// Iterates over the keys of a transaction, merging its own writes with the storage as of its
+/// start.
+pub struct TransactionIterator {
+    /// The writes of the transaction in the range, sorted by key.
+    writes: Vec<(Bytes, ValueKind, Bytes)>,
+    write_idx: usize,
+    storage: FusedIterator<LsmIterator>,
+    /// Whether the current entry is a write of the transaction.
+    is_write: bool,
+    reads: Arc<Mutex<ReadSet>>,
+}
+
+impl TransactionIterator {
+    fn new(
+        writes: Vec<(Bytes, ValueKind, Bytes)>,
+        storage: FusedIterator<LsmIterator>,
+        reads: Arc<Mutex<ReadSet>>,
+    ) -> Result<Self> {
+        let mut iter = Self {
+            writes,
+            write_idx: 0,
+            storage,
+            is_write: false,
+            reads,
+        };
+        iter.move_to_visible()?;
+        Ok(iter)
+    }
+
+    /// Move to the smaller key of the two sources. A write of the transaction hides the version
+    /// of the same key in the storage, and its deletes are skipped.
+    fn move_to_visible(&mut self) -> Result<()> {
+        while let Some((key, kind, _)) = self.writes.get(self.write_idx) {
+            if self.storage.is_valid() && self.storage.key() < &key[..] {
+                break;
+            }
+            if self.storage.is_valid() && self.storage.key() == &key[..] {
+                self.storage.next()?;
+            }
+            if *kind == ValueKind::Put {
+                self.is_write = true;
+                return Ok(());
+            }
+            self.write_idx += 1;
+        }
+        self.is_write = false;
+        if self.storage.is_valid() {
+            self.reads
+                .lock()
+                .keys
+                .insert(Bytes::copy_from_slice(self.storage.key()));
+        }
+        Ok(())
+    }
+}
+
+impl StorageIterator for TransactionIterator {
+    fn value(&self) -> &[u8] {
+        if self.is_write {
+            &self.writes[self.write_idx].2
+        } else {
+            self.storage.value()
+        }
+    }
+
+    fn key(&self) -> &[u8] {
+        if self.is_write {
+            &self.writes[self.write_idx].0
+        } else {
+            self.storage.key()
+        }
+    }
+
+    fn kind(&self) -> ValueKind {
+        ValueKind::Put
+    }
+
+    /// The writes of the transaction are not committed yet, so they are newer than any version
+    /// in the storage.
+    fn seq(&self) -> u64 {
+        if self.is_write {
+            MAX_SEQ
+        } else {
+            self.storage.seq()
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.is_write || self.storage.is_valid()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if self.is_write {
+            self.write_idx += 1;
+        } else {
+            self.storage.next()?;
+        }
+        self.move_to_visible()
+    }
+}
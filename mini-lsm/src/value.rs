@@ -0,0 +1,36 @@
+use anyhow::{bail, Result};
+
+/// Whether an entry sets its key to a value, deletes it, or is a merge operand to apply to the
+/// older versions. The value of a delete is always empty, but an empty value on its own does not
+/// mean a delete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    Put,
+    Delete,
+    Merge,
+}
+
+impl ValueKind {
+    const PUT: u8 = 0;
+    const DELETE: u8 = 1;
+    const MERGE: u8 = 2;
+
+    /// Encode the kind as a tag byte.
+    pub fn encode(self) -> u8 {
+        match self {
+            ValueKind::Put => Self::PUT,
+            ValueKind::Delete => Self::DELETE,
+            ValueKind::Merge => Self::MERGE,
+        }
+    }
+
+    /// Decode the kind from a tag byte.
+    pub fn decode(tag: u8) -> Result<Self> {
+        match tag {
+            Self::PUT => Ok(ValueKind::Put),
+            Self::DELETE => Ok(ValueKind::Delete),
+            Self::MERGE => Ok(ValueKind::Merge),
+            tag => bail!("unknown value kind {}", tag),
+        }
+    }
+}
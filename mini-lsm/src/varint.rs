@@ -0,0 +1,45 @@
+use bytes::{Buf, BufMut};
+
+/// Maximum length of an encoded u64.
+pub const MAX_VARINT_LEN: usize = 10;
+
+/// Append `value` to `buf` as a varint (LEB128): 7 bits per byte, least significant group first,
+/// with the high bit set on every byte but the last.
+pub fn put_varint(buf: &mut impl BufMut, mut value: u64) {
+    while value >= 0x80 {
+        buf.put_u8(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.put_u8(value as u8);
+}
+
+/// Read a varint from `buf`.
+///
+/// # Panics
+///
+/// Like the `get_*` methods of [`Buf`], panics if `buf` ends in the middle of the varint.
+pub fn get_varint(buf: &mut impl Buf) -> u64 {
+    let mut value = 0;
+    for shift in (0..MAX_VARINT_LEN * 7).step_by(7) {
+        let byte = buf.get_u8();
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+    }
+    panic!("varint is too long");
+}
+
+/// Get the number of bytes `value` takes as a varint.
+pub fn varint_len(value: u64) -> usize {
+    let mut len = 1;
+    let mut value = value >> 7;
+    while value > 0 {
+        len += 1;
+        value >>= 7;
+    }
+    len
+}
+
+#[cfg(test)]
+mod tests;
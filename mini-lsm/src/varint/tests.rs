@@ -0,0 +1,36 @@
+use super::*;
+
+#[test]
+fn test_varint_roundtrip() {
+    let values = [
+        0,
+        1,
+        127,
+        128,
+        300,
+        16383,
+        16384,
+        u32::MAX as u64,
+        u64::MAX - 1,
+        u64::MAX,
+    ];
+    let mut buf = Vec::new();
+    for value in values {
+        let len = buf.len();
+        put_varint(&mut buf, value);
+        assert_eq!(buf.len() - len, varint_len(value));
+    }
+    let mut rbuf = &buf[..];
+    for value in values {
+        assert_eq!(get_varint(&mut rbuf), value);
+    }
+    assert!(rbuf.is_empty());
+}
+
+#[test]
+fn test_varint_encoding() {
+    let mut buf = Vec::new();
+    put_varint(&mut buf, 300);
+    assert_eq!(buf, [0xac, 0x02]);
+    assert_eq!(varint_len(u64::MAX), MAX_VARINT_LEN);
+}
@@ -0,0 +1,237 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes};
+use crossbeam_skiplist::SkipMap;
+use parking_lot::Mutex;
+
+use crate::checksum::crc32;
+use crate::key::InternalKey;
+use crate::value::ValueKind;
+use crate::write_batch::WriteBatch;
+
+const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+const SIZEOF_U64: usize = std::mem::size_of::<u64>();
+
+/// The first 4 bytes of a WAL, "mwal" in ASCII.
+const MAGIC: u32 = 0x6d77_616c;
+/// The format of the records.
+const FORMAT_VERSION: u32 = 1;
+/// Size of the header: the magic number and the format version (u32 each).
+const HEADER_SIZE: usize = SIZEOF_U32 * 2;
+
+/// Decides when the write-ahead log is `fsync`ed to the disk.
+///
+/// Every record is handed to the OS as soon as it is appended, so a process crash never loses an
+/// acknowledged write. The policy only controls how much can be lost on a power failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalSyncPolicy {
+    /// `fsync` after every write.
+    PerWrite,
+    /// `fsync` once every `n` writes, so that a group of writes shares one `fsync`.
+    Group(usize),
+    /// Never `fsync` on write, only when the storage is synced or closed.
+    Buffered,
+}
+
+struct WalFile {
+    file: File,
+    /// Number of records written since the last `fsync`.
+    unsynced: usize,
+}
+
+/// A write-ahead log of a memtable.
+///
+/// The log starts with a header of a magic number and the format version (u32 each). Each record
+/// after it is encoded as `len (u32) | body | checksum (u32)`, where the checksum is the CRC-32 of
+/// the length and the body. The body is a write batch, encoded as `seq (u64) | count (u32) |
+/// entries`, where each of the `count` entries is `kind (u8) | key_len (u32) | key | value_len
+/// (u32) | value`.
+pub struct Wal {
+    file: Mutex<WalFile>,
+    policy: WalSyncPolicy,
+}
+
+impl Wal {
+    /// Create a new, empty log at `path`.
+    pub fn create(path: impl AsRef<Path>, policy: WalSyncPolicy) -> Result<Self> {
+        let mut file = OpenOptions::new().create_new(true).write(true).open(path)?;
+        file.write_all(&Self::encode_header())?;
+        Ok(Self {
+            file: Mutex::new(WalFile { file, unsynced: 0 }),
+            policy,
+        })
+    }
+
+    /// Replay the log at `path` into `map` and reopen it for appending.
+    ///
+    /// A record cut short by a crash, or whose checksum does not match, is dropped together with
+    /// anything after it, and the file is truncated so that new records are appended after the last
+    /// valid one. A batch is thus either replayed in full or not at all. A record that has a
+    /// matching checksum but cannot be decoded is reported as corruption.
+    ///
+    /// A log without the header, as written before the header existed, or of an unknown format
+    /// version, fails to open and is left as it is.
+    pub fn recover(
+        path: impl AsRef<Path>,
+        policy: WalSyncPolicy,
+        map: &SkipMap<InternalKey, (ValueKind, Bytes)>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new().read(true).append(true).open(path)?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        let header = Self::encode_header();
+        if buf.len() < HEADER_SIZE && header.starts_with(&buf) {
+            // A crash right after the log was created, before anything was appended.
+            file.set_len(0)?;
+            file.write_all(&header)?;
+            file.sync_all()?;
+            return Ok(Self {
+                file: Mutex::new(WalFile { file, unsynced: 0 }),
+                policy,
+            });
+        }
+        let mut rbuf = &buf[..];
+        if rbuf.remaining() < HEADER_SIZE || rbuf.get_u32() != MAGIC {
+            bail!(
+                "{} is not a WAL, or was written by an older version without a header",
+                path.display()
+            );
+        }
+        let version = rbuf.get_u32();
+        if version != FORMAT_VERSION {
+            bail!(
+                "{} has unknown WAL format version {}",
+                path.display(),
+                version
+            );
+        }
+        let mut valid_len = HEADER_SIZE;
+        while let Some(entries) = Self::decode_record(&mut rbuf)? {
+            for (key, kind, value) in entries {
+                map.insert(key, (kind, value));
+            }
+            valid_len = buf.len() - rbuf.len();
+        }
+        if valid_len < buf.len() {
+            file.set_len(valid_len as u64)?;
+            file.sync_all()?;
+        }
+        Ok(Self {
+            file: Mutex::new(WalFile { file, unsynced: 0 }),
+            policy,
+        })
+    }
+
+    fn encode_header() -> [u8; HEADER_SIZE] {
+        let mut header = [0; HEADER_SIZE];
+        let mut buf = &mut header[..];
+        buf.put_u32(MAGIC);
+        buf.put_u32(FORMAT_VERSION);
+        header
+    }
+
+    /// Decode one record, or return `None` if the buffer does not hold a complete record with a
+    /// matching checksum.
+    fn decode_record(buf: &mut &[u8]) -> Result<Option<Vec<(InternalKey, ValueKind, Bytes)>>> {
+        if buf.remaining() < SIZEOF_U32 {
+            return Ok(None);
+        }
+        let len = (&buf[..SIZEOF_U32]).get_u32() as usize;
+        if buf.remaining() < SIZEOF_U32 + len + SIZEOF_U32 {
+            return Ok(None);
+        }
+        let (record, rest) = buf.split_at(SIZEOF_U32 + len);
+        if crc32(record) != (&rest[..SIZEOF_U32]).get_u32() {
+            return Ok(None);
+        }
+        let entries = Self::decode_batch(&record[SIZEOF_U32..])?;
+        *buf = &rest[SIZEOF_U32..];
+        Ok(Some(entries))
+    }
+
+    /// Decode the batch of a record. The checksum of the record matched, so anything that does not
+    /// decode is corruption rather than a torn write.
+    fn decode_batch(mut buf: &[u8]) -> Result<Vec<(InternalKey, ValueKind, Bytes)>> {
+        if buf.remaining() < SIZEOF_U64 + SIZEOF_U32 {
+            bail!("WAL record is too short for a batch");
+        }
+        let seq = buf.get_u64();
+        let count = buf.get_u32() as usize;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            if buf.remaining() < 1 + SIZEOF_U32 {
+                bail!("WAL record is too short for its entries");
+            }
+            let kind = ValueKind::decode(buf.get_u8())?;
+            let key_len = buf.get_u32() as usize;
+            if buf.remaining() < key_len + SIZEOF_U32 {
+                bail!("WAL record is too short for its entries");
+            }
+            let key = buf.copy_to_bytes(key_len);
+            let value_len = buf.get_u32() as usize;
+            if buf.remaining() < value_len {
+                bail!("WAL record is too short for its entries");
+            }
+            let value = buf.copy_to_bytes(value_len);
+            entries.push((InternalKey::new(key, seq), kind, value));
+        }
+        if buf.has_remaining() {
+            bail!("WAL record has {} bytes after its entries", buf.remaining());
+        }
+        Ok(entries)
+    }
+
+    /// Append a batch to the log as one record, with all its entries at version `seq`.
+    pub fn append(&self, seq: u64, batch: &WriteBatch) -> Result<()> {
+        let size = batch
+            .entries()
+            .iter()
+            .map(|entry| 1 + SIZEOF_U32 * 2 + entry.key.len() + entry.value.len())
+            .sum::<usize>();
+        let mut buf = Vec::with_capacity(SIZEOF_U32 * 3 + SIZEOF_U64 + size);
+        buf.put_u32((SIZEOF_U64 + SIZEOF_U32 + size) as u32);
+        buf.put_u64(seq);
+        buf.put_u32(batch.len() as u32);
+        for entry in batch.entries() {
+            buf.put_u8(entry.kind.encode());
+            buf.put_u32(entry.key.len() as u32);
+            buf.put_slice(&entry.key);
+            buf.put_u32(entry.value.len() as u32);
+            buf.put_slice(&entry.value);
+        }
+        buf.put_u32(crc32(&buf));
+
+        let mut guard = self.file.lock();
+        // Write the record with a single call, so that a crash leaves at most one torn record at
+        // the end of the log.
+        guard.file.write_all(&buf)?;
+        guard.unsynced += 1;
+        let need_sync = match self.policy {
+            WalSyncPolicy::PerWrite => true,
+            WalSyncPolicy::Group(n) => guard.unsynced >= n,
+            WalSyncPolicy::Buffered => false,
+        };
+        if need_sync {
+            guard.file.sync_data()?;
+            guard.unsynced = 0;
+        }
+        Ok(())
+    }
+
+    /// `fsync` all records written so far.
+    pub fn sync(&self) -> Result<()> {
+        let mut guard = self.file.lock();
+        if guard.unsynced > 0 {
+            guard.file.sync_data()?;
+            guard.unsynced = 0;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
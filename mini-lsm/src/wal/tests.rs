@@ -0,0 +1,212 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use bytes::Bytes;
+use crossbeam_skiplist::SkipMap;
+use tempfile::tempdir;
+
+use super::{Wal, WalSyncPolicy, HEADER_SIZE};
+use crate::checksum::crc32;
+use crate::key::InternalKey;
+use crate::value::ValueKind;
+use crate::write_batch::WriteBatch;
+
+fn get(
+    map: &SkipMap<InternalKey, (ValueKind, Bytes)>,
+    key: &'static [u8],
+    seq: u64,
+) -> (ValueKind, Bytes) {
+    map.get(&InternalKey::new(Bytes::from_static(key), seq))
+        .unwrap()
+        .value()
+        .clone()
+}
+
+#[test]
+fn test_wal_recover() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    {
+        let wal = Wal::create(&path, WalSyncPolicy::Group(2)).unwrap();
+        wal.append(1, WriteBatch::new().put(b"key1", b"value1"))
+            .unwrap();
+        wal.append(2, WriteBatch::new().put(b"key2", b"value2"))
+            .unwrap();
+        wal.append(3, WriteBatch::new().put(b"key3", b"")).unwrap();
+        wal.append(4, WriteBatch::new().delete(b"key1")).unwrap();
+        wal.sync().unwrap();
+    }
+    let map = SkipMap::new();
+    Wal::recover(&path, WalSyncPolicy::PerWrite, &map).unwrap();
+    assert_eq!(map.len(), 4);
+    assert_eq!(
+        get(&map, b"key1", 1),
+        (ValueKind::Put, Bytes::from("value1"))
+    );
+    assert_eq!(get(&map, b"key1", 4), (ValueKind::Delete, Bytes::new()));
+    assert_eq!(
+        get(&map, b"key2", 2),
+        (ValueKind::Put, Bytes::from("value2"))
+    );
+    assert_eq!(get(&map, b"key3", 3), (ValueKind::Put, Bytes::new()));
+}
+
+#[test]
+fn test_wal_recover_torn_record() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    {
+        let wal = Wal::create(&path, WalSyncPolicy::Buffered).unwrap();
+        wal.append(1, WriteBatch::new().put(b"key1", b"value1"))
+            .unwrap();
+    }
+    // Simulate a crash in the middle of appending a record.
+    {
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[
+            0, 0, 0, 30, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 0, 4, b'k', b'e',
+        ])
+        .unwrap();
+    }
+    {
+        let map = SkipMap::new();
+        let wal = Wal::recover(&path, WalSyncPolicy::PerWrite, &map).unwrap();
+        assert_eq!(map.len(), 1);
+        wal.append(2, WriteBatch::new().put(b"key2", b"value2"))
+            .unwrap();
+    }
+    let map = SkipMap::new();
+    Wal::recover(&path, WalSyncPolicy::PerWrite, &map).unwrap();
+    assert_eq!(map.len(), 2);
+    assert_eq!(
+        get(&map, b"key1", 1),
+        (ValueKind::Put, Bytes::from("value1"))
+    );
+    assert_eq!(
+        get(&map, b"key2", 2),
+        (ValueKind::Put, Bytes::from("value2"))
+    );
+}
+
+#[test]
+fn test_wal_recover_batch() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    {
+        let wal = Wal::create(&path, WalSyncPolicy::PerWrite).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(b"key1", b"value1").put(b"key2", b"value2");
+        wal.append(1, &batch).unwrap();
+        batch.clear();
+        batch.delete(b"key1").put(b"key3", b"value3");
+        wal.append(2, &batch).unwrap();
+    }
+    // Cut the second batch after its first entry, which must not be replayed on its own.
+    let len = std::fs::metadata(&path).unwrap().len();
+    OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(len - 10)
+        .unwrap();
+    let map = SkipMap::new();
+    Wal::recover(&path, WalSyncPolicy::PerWrite, &map).unwrap();
+    assert_eq!(map.len(), 2);
+    assert_eq!(
+        get(&map, b"key1", 1),
+        (ValueKind::Put, Bytes::from("value1"))
+    );
+    assert_eq!(
+        get(&map, b"key2", 1),
+        (ValueKind::Put, Bytes::from("value2"))
+    );
+}
+
+#[test]
+fn test_wal_recover_checksum_mismatch() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    {
+        let wal = Wal::create(&path, WalSyncPolicy::PerWrite).unwrap();
+        for seq in 1..=3 {
+            wal.append(seq, WriteBatch::new().put(b"key", b"value"))
+                .unwrap();
+        }
+    }
+    // Flip a byte in the value of the second record, keeping its length.
+    let mut data = std::fs::read(&path).unwrap();
+    let record_len = (data.len() - HEADER_SIZE) / 3;
+    data[HEADER_SIZE + record_len * 2 - 5] ^= 1;
+    std::fs::write(&path, &data).unwrap();
+
+    let map = SkipMap::new();
+    Wal::recover(&path, WalSyncPolicy::PerWrite, &map).unwrap();
+    assert_eq!(map.len(), 1);
+    assert_eq!(get(&map, b"key", 1), (ValueKind::Put, Bytes::from("value")));
+    assert_eq!(
+        std::fs::metadata(&path).unwrap().len(),
+        (HEADER_SIZE + record_len) as u64
+    );
+}
+
+#[test]
+fn test_wal_recover_unknown_kind() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    {
+        let wal = Wal::create(&path, WalSyncPolicy::PerWrite).unwrap();
+        wal.append(1, WriteBatch::new().put(b"key1", b"value1"))
+            .unwrap();
+        wal.append(2, WriteBatch::new().put(b"key2", b"value2"))
+            .unwrap();
+    }
+    // Replace the kind of the first entry with an unknown one, and fix up the checksum.
+    let mut data = std::fs::read(&path).unwrap();
+    let record = &mut data[HEADER_SIZE..];
+    let record_len = record.len() / 2;
+    record[16] = 0xff;
+    let checksum = crc32(&record[..record_len - 4]);
+    record[record_len - 4..record_len].copy_from_slice(&checksum.to_be_bytes());
+    std::fs::write(&path, &data).unwrap();
+
+    let map = SkipMap::new();
+    assert!(Wal::recover(&path, WalSyncPolicy::PerWrite, &map).is_err());
+    // Nothing is truncated, so the records are still there to be looked at.
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+}
+
+#[test]
+fn test_wal_recover_header() {
+    let dir = tempdir().unwrap();
+    // A crash right after creating the log may leave the header incomplete.
+    let path = dir.path().join("1.wal");
+    std::fs::write(&path, &Wal::encode_header()[..3]).unwrap();
+    {
+        let map = SkipMap::new();
+        let wal = Wal::recover(&path, WalSyncPolicy::PerWrite, &map).unwrap();
+        assert!(map.is_empty());
+        wal.append(1, WriteBatch::new().put(b"key1", b"value1"))
+            .unwrap();
+    }
+    let map = SkipMap::new();
+    Wal::recover(&path, WalSyncPolicy::PerWrite, &map).unwrap();
+    assert_eq!(
+        get(&map, b"key1", 1),
+        (ValueKind::Put, Bytes::from("value1"))
+    );
+
+    // A log without a header is refused and left alone.
+    let path = dir.path().join("2.wal");
+    let data = [0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 1, b'k'];
+    std::fs::write(&path, data).unwrap();
+    assert!(Wal::recover(&path, WalSyncPolicy::PerWrite, &SkipMap::new()).is_err());
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+
+    // So is a log of an unknown version.
+    let path = dir.path().join("3.wal");
+    let mut data = Wal::encode_header();
+    data[HEADER_SIZE - 1] = 0xff;
+    std::fs::write(&path, data).unwrap();
+    assert!(Wal::recover(&path, WalSyncPolicy::PerWrite, &SkipMap::new()).is_err());
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+}
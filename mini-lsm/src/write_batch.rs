@@ -0,0 +1,138 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+use crate::merge_operator::MergeOperator;
+use crate::value::ValueKind;
+
+/// One write of a batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BatchEntry {
+    pub(crate) kind: ValueKind,
+    pub(crate) key: Bytes,
+    pub(crate) value: Bytes,
+    /// Merge operands added after the write, from the oldest to the newest, which are combined
+    /// with it when the batch is written.
+    operands: Vec<Bytes>,
+}
+
+/// A group of puts, deletes and merges that is applied atomically by `LsmStorage::write`.
+///
+/// All the writes of a batch get the same sequence number, so readers see either all of them or
+/// none. The writes of a key in a batch are collapsed into one as they are added, so that they
+/// apply in order: a put or delete replaces the earlier writes of the key, and a merge operand is
+/// combined with the earlier write of the key by the merge operator when the batch is written.
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    entries: Vec<BatchEntry>,
+    /// The position of the entry of each key in `entries`.
+    positions: HashMap<Bytes, usize>,
+}
+
+impl WriteBatch {
+    /// Create an empty batch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a put of a key-value pair to the batch.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> &mut Self {
+        self.push(ValueKind::Put, key, value)
+    }
+
+    /// Add a delete of a key to the batch.
+    pub fn delete(&mut self, key: &[u8]) -> &mut Self {
+        self.push(ValueKind::Delete, key, b"")
+    }
+
+    /// Add a merge operand of a key to the batch. It is applied after the earlier writes of the
+    /// key, including those in the batch.
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) -> &mut Self {
+        assert!(!key.is_empty(), "key cannot be empty");
+
+        match self.positions.get(key) {
+            Some(&position) => {
+                let operand = Bytes::copy_from_slice(operand);
+                self.entries[position].operands.push(operand);
+                self
+            }
+            None => self.push(ValueKind::Merge, key, operand),
+        }
+    }
+
+    fn push(&mut self, kind: ValueKind, key: &[u8], value: &[u8]) -> &mut Self {
+        assert!(!key.is_empty(), "key cannot be empty");
+
+        let entry = BatchEntry {
+            kind,
+            key: Bytes::copy_from_slice(key),
+            value: Bytes::copy_from_slice(value),
+            operands: Vec::new(),
+        };
+        match self.positions.get(key) {
+            Some(&position) => self.entries[position] = entry,
+            None => {
+                self.positions.insert(entry.key.clone(), self.entries.len());
+                self.entries.push(entry);
+            }
+        }
+        self
+    }
+
+    /// Get the number of keys written by the batch.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if the batch has no writes.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Remove all writes from the batch, so that it can be reused.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.positions.clear();
+    }
+
+    pub(crate) fn entries(&self) -> &[BatchEntry] {
+        &self.entries
+    }
+
+    /// Combine the merge operands added after another write of their key with that write, so that
+    /// each key has a single entry to write.
+    pub(crate) fn resolve_operands(
+        &self,
+        operator: Option<&Arc<dyn MergeOperator>>,
+    ) -> Result<Cow<'_, Self>> {
+        if self.entries.iter().all(|entry| entry.operands.is_empty()) {
+            return Ok(Cow::Borrowed(self));
+        }
+        let Some(operator) = operator else {
+            bail!("cannot merge without a merge operator");
+        };
+        let mut batch = self.clone();
+        for entry in batch.entries.iter_mut() {
+            if entry.operands.is_empty() {
+                continue;
+            }
+            let operands = std::mem::take(&mut entry.operands);
+            (entry.kind, entry.value) = match entry.kind {
+                ValueKind::Put => (
+                    ValueKind::Put,
+                    operator.merge(&entry.key, Some(&entry.value), &operands)?,
+                ),
+                ValueKind::Delete => (ValueKind::Put, operator.merge(&entry.key, None, &operands)?),
+                ValueKind::Merge => {
+                    let mut all = vec![entry.value.clone()];
+                    all.extend(operands);
+                    (ValueKind::Merge, operator.partial_merge(&entry.key, &all)?)
+                }
+            };
+        }
+        Ok(Cow::Owned(batch))
+    }
+}
@@ -0,0 +1,154 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::compact::CompactionController;
+use crate::lsm_storage::{LsmStorageCore, LsmStorageInner, BACKGROUND_CHECK_INTERVAL};
+
+/// Options of write stalls. Once the background threads fall behind past a slowdown trigger, each
+/// write is delayed. Past a stop trigger, writes wait until the background threads catch up.
+#[derive(Debug, Clone)]
+pub struct WriteStallOptions {
+    /// Number of immutable memtables at which writes are delayed.
+    pub imm_memtables_slowdown_trigger: usize,
+    /// Number of immutable memtables at which writes are stopped.
+    pub imm_memtables_stop_trigger: usize,
+    /// Number of L0 SSTs at which writes are delayed.
+    pub level0_slowdown_writes_trigger: usize,
+    /// Number of L0 SSTs at which writes are stopped.
+    pub level0_stop_writes_trigger: usize,
+    /// Estimated bytes to compact at which writes are delayed.
+    pub soft_pending_compaction_bytes_limit: u64,
+    /// Estimated bytes to compact at which writes are stopped.
+    pub hard_pending_compaction_bytes_limit: u64,
+    /// How long each write is delayed by.
+    pub slowdown_delay: Duration,
+}
+
+impl Default for WriteStallOptions {
+    fn default() -> Self {
+        Self {
+            imm_memtables_slowdown_trigger: 3,
+            imm_memtables_stop_trigger: 4,
+            level0_slowdown_writes_trigger: 20,
+            level0_stop_writes_trigger: 36,
+            soft_pending_compaction_bytes_limit: 64 << 30,
+            hard_pending_compaction_bytes_limit: 256 << 30,
+            slowdown_delay: Duration::from_millis(1),
+        }
+    }
+}
+
+/// The reason of a write stall.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteStallReason {
+    /// Too many immutable memtables are waiting to be flushed.
+    ImmMemtables,
+    /// Too many SSTs are in L0.
+    Level0Files,
+    /// Too much data is waiting to be compacted.
+    PendingCompactionBytes,
+}
+
+/// Whether writes are currently stalled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteStallCondition {
+    Normal,
+    Delayed(WriteStallReason),
+    Stopped(WriteStallReason),
+}
+
+impl LsmStorageCore {
+    pub(crate) fn write_stall_condition_of(
+        &self,
+        snapshot: &LsmStorageInner,
+    ) -> WriteStallCondition {
+        let options = &self.options.write_stall_options;
+        let imm_memtables = snapshot.imm_memtables.len();
+        let l0_files = match self.compaction_controller {
+            // FIFO compaction keeps all SSTs in L0, so their number does not tell whether
+            // compaction falls behind.
+            CompactionController::Fifo(_) => 0,
+            _ => snapshot.l0_sstables.len(),
+        };
+        let pending_bytes = self
+            .compaction_controller
+            .estimate_pending_compaction_bytes(snapshot);
+
+        if imm_memtables >= options.imm_memtables_stop_trigger {
+            WriteStallCondition::Stopped(WriteStallReason::ImmMemtables)
+        } else if l0_files >= options.level0_stop_writes_trigger {
+            WriteStallCondition::Stopped(WriteStallReason::Level0Files)
+        } else if pending_bytes >= options.hard_pending_compaction_bytes_limit {
+            WriteStallCondition::Stopped(WriteStallReason::PendingCompactionBytes)
+        } else if imm_memtables >= options.imm_memtables_slowdown_trigger {
+            WriteStallCondition::Delayed(WriteStallReason::ImmMemtables)
+        } else if l0_files >= options.level0_slowdown_writes_trigger {
+            WriteStallCondition::Delayed(WriteStallReason::Level0Files)
+        } else if pending_bytes >= options.soft_pending_compaction_bytes_limit {
+            WriteStallCondition::Delayed(WriteStallReason::PendingCompactionBytes)
+        } else {
+            WriteStallCondition::Normal
+        }
+    }
+
+    /// Get whether writes are currently stalled, and why.
+    pub fn write_stall_condition(&self) -> WriteStallCondition {
+        let snapshot = self.inner.read().clone();
+        self.write_stall_condition_of(&snapshot)
+    }
+
+    /// Delay or block the caller according to the current write stall condition. Fails if a
+    /// background flush or compaction failed, since the stall may never clear then.
+    pub(crate) fn wait_for_write_stall(&self) -> Result<()> {
+        loop {
+            self.check_background_error()?;
+            let snapshot = self.inner.read().clone();
+            let reason = match self.write_stall_condition_of(&snapshot) {
+                WriteStallCondition::Normal => return Ok(()),
+                WriteStallCondition::Delayed(_) => {
+                    std::thread::sleep(self.options.write_stall_options.slowdown_delay);
+                    return Ok(());
+                }
+                WriteStallCondition::Stopped(reason) => reason,
+            };
+            // If the compaction controller has nothing to do, e.g. because there are no levels to
+            // compact L0 into, waiting for compaction would never end.
+            if reason != WriteStallReason::ImmMemtables
+                && self
+                    .compaction_controller
+                    .generate_task(&snapshot)
+                    .is_none()
+            {
+                return Ok(());
+            }
+
+            let background_thread_running = match reason {
+                WriteStallReason::ImmMemtables => self.notify_flush(),
+                WriteStallReason::Level0Files | WriteStallReason::PendingCompactionBytes => {
+                    self.notify_compaction()
+                }
+            };
+            if !background_thread_running {
+                // Nobody is going to catch up if the storage is closed, so do the work on this
+                // thread once and let the write go through.
+                match reason {
+                    WriteStallReason::ImmMemtables => self.flush_imm_memtables()?,
+                    WriteStallReason::Level0Files | WriteStallReason::PendingCompactionBytes => {
+                        self.compact()?
+                    }
+                }
+                return Ok(());
+            }
+
+            let mut guard = self.background_work_done_lock.lock();
+            // Check again with the lock held, so that a notification is not missed.
+            if self.check_background_error().is_ok() {
+                if let WriteStallCondition::Stopped(_) = self.write_stall_condition() {
+                    self.background_work_done
+                        .wait_for(&mut guard, BACKGROUND_CHECK_INTERVAL);
+                }
+            }
+        }
+    }
+}